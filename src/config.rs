@@ -0,0 +1,436 @@
+use crate::log::Level;
+use crate::{info, warn};
+
+extern crate alloc;
+use alloc::string::{String, ToString};
+use alloc::vec;
+
+use uefi::boot;
+use uefi::proto::loaded_image::LoadedImage;
+
+use crate::loader::read_entire_file;
+use crate::util::BootError;
+
+pub const CONFIG_PATH: &str = r"\a9nloader\loader.conf";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoMode {
+    // keep the mode the firmware handed over
+    Current,
+    // largest resolution the GOP offers
+    Max,
+    // preferred timing of the attached display (EDID), falls back to Max
+    Native,
+    // pick from an interactive list at startup
+    Menu,
+    Resolution { width: usize, height: usize },
+}
+
+impl VideoMode {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "current" | "auto" => Some(VideoMode::Current),
+            "max" => Some(VideoMode::Max),
+            "native" => Some(VideoMode::Native),
+            "menu" | "ask" => Some(VideoMode::Menu),
+            _ => {
+                let (width, height) = value.split_once('x')?;
+                Some(VideoMode::Resolution {
+                    width: width.trim().parse().ok()?,
+                    height: height.trim().parse().ok()?,
+                })
+            }
+        }
+    }
+}
+
+// outputs for print!/log, `console=gop,conout,serial`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsoleSelection {
+    pub gop: bool,
+    pub conout: bool,
+    pub serial: bool,
+}
+
+impl ConsoleSelection {
+    fn parse(value: &str) -> Option<Self> {
+        let mut selection = ConsoleSelection {
+            gop: false,
+            conout: false,
+            serial: false,
+        };
+
+        for name in value.split(',').map(str::trim) {
+            match name {
+                "gop" | "graphics" => selection.gop = true,
+                "conout" | "text" => selection.conout = true,
+                "serial" => selection.serial = true,
+                _ => return None,
+            }
+        }
+        Some(selection)
+    }
+}
+
+// `volume=`: where the kernel and init live
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VolumeSelector {
+    // the loader's own volume
+    Loader,
+    // first volume that has the kernel
+    Auto,
+    // best A9N system partition (by GPT type GUID) on any disk
+    A9nPartition,
+    // GPT unique partition GUID
+    PartitionGuid(uefi::Guid),
+    Label(String),
+    // TFTP server over the first network interface that gets a DHCP lease; None for the
+    // server DHCP names
+    Tftp(Option<[u8; 4]>),
+}
+
+impl VolumeSelector {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "loader" | "self" => Some(VolumeSelector::Loader),
+            "auto" => Some(VolumeSelector::Auto),
+            "a9n" => Some(VolumeSelector::A9nPartition),
+            "tftp" => Some(VolumeSelector::Tftp(None)),
+            _ => {
+                if let Some(label) = value.strip_prefix("label:") {
+                    Some(VolumeSelector::Label(label.to_string()))
+                } else if let Some(server) = value.strip_prefix("tftp:") {
+                    parse_ipv4(server).map(|server| VolumeSelector::Tftp(Some(server)))
+                } else {
+                    uefi::Guid::try_parse(value)
+                        .ok()
+                        .map(VolumeSelector::PartitionGuid)
+                }
+            }
+        }
+    }
+}
+
+// `kernel.address=`: where each kernel PT_LOAD goes in physical memory (and, the same way,
+// where the entry point is jumped to)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressPolicy {
+    // p_paddr as linked
+    Physical,
+    // p_paddr with the higher half bits cleared
+    Mask,
+    // p_vaddr minus the kernel's virtual base
+    VirtualOffset(usize),
+}
+
+impl AddressPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "paddr" => Some(AddressPolicy::Physical),
+            "mask" => Some(AddressPolicy::Mask),
+            _ => value
+                .strip_prefix("vma:")
+                .map(|offset| offset.trim_start_matches("0x"))
+                .and_then(|offset| usize::from_str_radix(offset, 16).ok())
+                .map(AddressPolicy::VirtualOffset),
+        }
+    }
+}
+
+impl core::fmt::Display for AddressPolicy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            AddressPolicy::Physical => write!(f, "paddr"),
+            AddressPolicy::Mask => write!(f, "mask"),
+            AddressPolicy::VirtualOffset(offset) => write!(f, "vma:0x{:x}", offset),
+        }
+    }
+}
+
+fn parse_ipv4(value: &str) -> Option<[u8; 4]> {
+    let mut address = [0u8; 4];
+    let mut octets = value.split('.');
+    for octet in address.iter_mut() {
+        *octet = octets.next()?.parse().ok()?;
+    }
+    octets.next().is_none().then_some(address)
+}
+
+impl core::fmt::Display for VolumeSelector {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            VolumeSelector::Loader => write!(f, "loader"),
+            VolumeSelector::Auto => write!(f, "auto"),
+            VolumeSelector::A9nPartition => write!(f, "a9n"),
+            VolumeSelector::PartitionGuid(guid) => write!(f, "{}", guid),
+            VolumeSelector::Label(label) => write!(f, "label:{}", label),
+            VolumeSelector::Tftp(None) => write!(f, "tftp"),
+            VolumeSelector::Tftp(Some(server)) => {
+                write!(f, "tftp:{}", crate::loader::format_ipv4(*server))
+            }
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "1" | "yes" | "on" | "true" => Some(true),
+        "0" | "no" | "off" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+// `chainload=<title>|<path>[|<load options>]`, one line per entry
+#[derive(Debug, Clone)]
+pub struct ChainloadEntry {
+    pub title: String,
+    pub path: String,
+    pub options: Option<String>,
+}
+
+impl ChainloadEntry {
+    fn parse(value: &str) -> Option<Self> {
+        let mut fields = value.splitn(3, '|').map(str::trim);
+        let title = fields.next().filter(|title| !title.is_empty())?;
+        let path = fields.next().filter(|path| !path.is_empty())?;
+        Some(ChainloadEntry {
+            title: title.to_string(),
+            path: path.to_string(),
+            options: fields.next().map(str::to_string),
+        })
+    }
+}
+
+// `entry=<title>|<volume>`: another way to boot A9N, with the kernel from somewhere else
+#[derive(Debug, Clone)]
+pub struct BootEntry {
+    pub title: String,
+    pub volume: VolumeSelector,
+}
+
+impl BootEntry {
+    fn parse(value: &str) -> Option<Self> {
+        let mut fields = value.splitn(2, '|').map(str::trim);
+        let title = fields.next().filter(|title| !title.is_empty())?;
+        let volume = fields.next()?;
+        Some(BootEntry {
+            title: title.to_string(),
+            volume: VolumeSelector::parse(volume)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub video: VideoMode,
+    pub splash: Option<String>,
+    // rows kept for paging back through the console
+    pub scrollback: usize,
+    // wait for a key before jumping to the kernel
+    pub pause: bool,
+    pub console: ConsoleSelection,
+    // write \a9nloader\boot.log, keeping `bootlog_keep` previous ones
+    pub bootlog: bool,
+    pub bootlog_keep: usize,
+    // A/B kernel slots under \kernel\a and \kernel\b, a trial slot gets `slot_tries` boots
+    pub slots: bool,
+    pub slot_tries: u8,
+    pub volume: VolumeSelector,
+    // None: whatever the kernel's A9N note asks for, or Mask without one
+    pub kernel_address: Option<AddressPolicy>,
+    // hand the kernel's (and init's) symbol tables to the kernel for backtraces
+    pub symbols: bool,
+    pub symbols_init: bool,
+    // boot menu entries besides A9N; the menu only shows up if there are any
+    pub entries: vec::Vec<BootEntry>,
+    pub chainload: vec::Vec<ChainloadEntry>,
+    // seconds before the menu boots A9N
+    pub menu_timeout: usize,
+    // global threshold, `loglevel=<level>`
+    pub log_level: Level,
+    // per-sink overrides, `log.<sink>=<level>`
+    pub sink_levels: vec::Vec<(String, Level)>,
+}
+
+impl Config {
+    pub const fn new() -> Self {
+        Config {
+            video: VideoMode::Current,
+            splash: None,
+            scrollback: 1024,
+            pause: false,
+            console: ConsoleSelection {
+                gop: true,
+                conout: true,
+                serial: false,
+            },
+            bootlog: false,
+            bootlog_keep: 0,
+            slots: false,
+            slot_tries: 3,
+            volume: VolumeSelector::Loader,
+            kernel_address: None,
+            symbols: true,
+            symbols_init: false,
+            entries: vec::Vec::new(),
+            chainload: vec::Vec::new(),
+            menu_timeout: 5,
+            log_level: Level::DEFAULT,
+            sink_levels: vec::Vec::new(),
+        }
+    }
+
+    pub fn log_level_for(&self, sink: &str) -> Level {
+        self.sink_levels
+            .iter()
+            .rev()
+            .find(|(name, _)| name == sink)
+            .map(|(_, level)| *level)
+            .unwrap_or(Level::Debug)
+    }
+
+    // apply a single `key=value` pair, returns false if the key or value is unknown
+    pub fn apply(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "video" => VideoMode::parse(value)
+                .map(|video| self.video = video)
+                .is_some(),
+            "splash" => {
+                self.splash = Some(value.to_string());
+                true
+            }
+            "scrollback" => value.parse().map(|rows| self.scrollback = rows).is_ok(),
+            "pause" => parse_bool(value).map(|pause| self.pause = pause).is_some(),
+            "symbols" => parse_bool(value)
+                .map(|symbols| self.symbols = symbols)
+                .is_some(),
+            "symbols.init" => parse_bool(value)
+                .map(|symbols| self.symbols_init = symbols)
+                .is_some(),
+            "bootlog" => parse_bool(value)
+                .map(|bootlog| self.bootlog = bootlog)
+                .is_some(),
+            "bootlog.keep" => value.parse().map(|keep| self.bootlog_keep = keep).is_ok(),
+            "slots" => parse_bool(value).map(|slots| self.slots = slots).is_some(),
+            "slots.tries" => value.parse().map(|tries| self.slot_tries = tries).is_ok(),
+            "volume" => VolumeSelector::parse(value)
+                .map(|volume| self.volume = volume)
+                .is_some(),
+            "kernel.address" => AddressPolicy::parse(value)
+                .map(|policy| self.kernel_address = Some(policy))
+                .is_some(),
+            "entry" => BootEntry::parse(value)
+                .map(|entry| self.entries.push(entry))
+                .is_some(),
+            "chainload" => ChainloadEntry::parse(value)
+                .map(|entry| self.chainload.push(entry))
+                .is_some(),
+            "menu.timeout" => value
+                .parse()
+                .map(|seconds| self.menu_timeout = seconds)
+                .is_ok(),
+            "loglevel" => Level::parse(value)
+                .map(|level| self.log_level = level)
+                .is_some(),
+            _ => match key.strip_prefix("log.") {
+                Some(sink) => Level::parse(value)
+                    .map(|level| self.sink_levels.push((sink.to_string(), level)))
+                    .is_some(),
+                None => false,
+            },
+        }
+    }
+
+    // config file: one `key=value` per line, `#` starts a comment
+    pub fn apply_file(&mut self, text: &str) {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            match line.split_once('=') {
+                Some((key, value)) => {
+                    if !self.apply(key.trim(), value.trim()) {
+                        warn!(
+                            "{}:{}: ignoring unknown setting '{}'",
+                            CONFIG_PATH,
+                            number + 1,
+                            line
+                        );
+                    }
+                }
+                None => warn!(
+                    "{}:{}: expected key=value, got '{}'",
+                    CONFIG_PATH,
+                    number + 1,
+                    line
+                ),
+            }
+        }
+    }
+
+    // load options: whitespace separated `key=value` tokens, anything else (e.g. the image
+    // name the shell prepends) is skipped
+    pub fn apply_load_options(&mut self, options: &str) {
+        for token in options.split_whitespace() {
+            if let Some((key, value)) = token.split_once('=')
+                && !self.apply(key, value)
+            {
+                warn!("Ignoring unknown load option '{}'", token);
+            }
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static mut CONFIG: Config = Config::new();
+
+pub fn current() -> &'static Config {
+    #[allow(static_mut_refs)]
+    unsafe {
+        &CONFIG
+    }
+}
+
+// the boot menu picked an entry with its own kernel volume
+pub fn set_volume(volume: VolumeSelector) {
+    unsafe { CONFIG.volume = volume };
+}
+
+// defaults <- config file on the loader volume <- load options
+pub fn load() {
+    let mut config = Config::new();
+
+    match read_entire_file(CONFIG_PATH) {
+        Ok(bytes) => match core::str::from_utf8(&bytes) {
+            Ok(text) => config.apply_file(text),
+            Err(_) => warn!("{} is not valid UTF-8, ignoring", CONFIG_PATH),
+        },
+        Err(BootError::FileNotFound { .. }) => {
+            info!("No config file at {}, using defaults", CONFIG_PATH)
+        }
+        Err(e) => warn!("Failed to read config, using defaults: {}", e),
+    }
+
+    if let Some(options) = load_options() {
+        info!("Load options: {}", options);
+        config.apply_load_options(&options);
+    }
+
+    unsafe { CONFIG = config };
+}
+
+fn load_options() -> Option<String> {
+    let loaded_image = boot::open_protocol_exclusive::<LoadedImage>(boot::image_handle()).ok()?;
+    loaded_image
+        .load_options_as_cstr16()
+        .ok()
+        .map(|options| options.to_string())
+        .filter(|options| !options.trim().is_empty())
+}
@@ -0,0 +1,382 @@
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec;
+
+use core::fmt;
+
+use uefi::Status;
+use uefi::boot::MemoryType;
+
+#[derive(Debug)]
+pub enum BootError {
+    // firmware call failed without anything more specific to say
+    Uefi(Status),
+    FileNotFound {
+        path: String,
+    },
+    FileRead {
+        path: String,
+        status: Status,
+    },
+    ElfParse {
+        reason: &'static str,
+    },
+    // parsed, but not something we can load
+    ElfInvalid(ElfViolation),
+    // on-disk structures we can't make sense of
+    FileSystem {
+        reason: &'static str,
+    },
+    // compressed image that doesn't decompress, or whose format support isn't built in
+    Decompression {
+        format: &'static str,
+        reason: &'static str,
+    },
+    // two PT_LOAD segments claim the same physical pages
+    SegmentOverlap {
+        first: (usize, usize),
+        second: (usize, usize),
+    },
+    // segment outside the file or the memory reserved for the image
+    SegmentOutOfBounds {
+        address: usize,
+        size: usize,
+        limit: usize,
+    },
+    AllocationFailed {
+        // None for AnyPages
+        address: Option<usize>,
+        pages: usize,
+        memory_type: MemoryType,
+        status: Status,
+    },
+    SymbolMissing {
+        name: String,
+    },
+    // nothing matched `volume=`
+    VolumeNotFound {
+        selector: String,
+    },
+    Panic {
+        message: String,
+    },
+    // what we were doing when `source` happened
+    Context {
+        context: String,
+        source: Box<BootError>,
+    },
+}
+
+// what validate_elf found wrong; segment indices count program headers, not just PT_LOADs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfViolation {
+    Class(u8),
+    Endianness(u8),
+    Version(u32),
+    Machine(u16),
+    FileType(u16),
+    // table entry size or bounds; `table` is "program" or "section"
+    HeaderTable {
+        table: &'static str,
+        offset: usize,
+        entry_size: usize,
+        count: usize,
+        file_size: usize,
+    },
+    NoLoadSegments,
+    TooManySegments {
+        count: usize,
+        max: usize,
+    },
+    SegmentOutsideFile {
+        index: usize,
+        offset: usize,
+        size: usize,
+        file_size: usize,
+    },
+    FileSizeExceedsMemorySize {
+        index: usize,
+        file_size: usize,
+        memory_size: usize,
+    },
+    Alignment {
+        index: usize,
+        align: usize,
+    },
+    // p_vaddr and p_offset disagree modulo p_align
+    Misaligned {
+        index: usize,
+        address: usize,
+        offset: usize,
+        align: usize,
+    },
+    AddressOverflow {
+        index: usize,
+    },
+    // the kernel address policy gives this segment no physical address
+    Untranslatable {
+        index: usize,
+        address: usize,
+    },
+    EntryPointNotExecutable {
+        entry: usize,
+    },
+    // section indices count section headers
+    SectionOutsideFile {
+        index: usize,
+        offset: usize,
+        size: usize,
+        file_size: usize,
+    },
+    // sh_link past the table, or a symbol table not linked to a string table
+    SectionLink {
+        index: usize,
+        link: usize,
+    },
+    // not a whole number of 8 byte aligned Elf64_Sym entries
+    SymbolTableLayout {
+        index: usize,
+    },
+}
+
+impl fmt::Display for ElfViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElfViolation::Class(class) => write!(f, "class {} is not ELF64", class),
+            ElfViolation::Endianness(data) => {
+                write!(f, "data encoding {} is not little endian", data)
+            }
+            ElfViolation::Version(version) => write!(f, "unknown ELF version {}", version),
+            ElfViolation::Machine(machine) => {
+                write!(f, "machine 0x{:x} is not x86_64", machine)
+            }
+            ElfViolation::FileType(file_type) => {
+                write!(f, "file type {} is not an executable", file_type)
+            }
+            ElfViolation::HeaderTable {
+                table,
+                offset,
+                entry_size,
+                count,
+                file_size,
+            } => write!(
+                f,
+                "{} header table ({} entries of {} bytes at 0x{:x}) doesn't fit the {} byte file",
+                table, count, entry_size, offset, file_size
+            ),
+            ElfViolation::NoLoadSegments => write!(f, "no PT_LOAD segments"),
+            ElfViolation::TooManySegments { count, max } => {
+                write!(
+                    f,
+                    "{} PT_LOAD segments, at most {} are supported",
+                    count, max
+                )
+            }
+            ElfViolation::SegmentOutsideFile {
+                index,
+                offset,
+                size,
+                file_size,
+            } => write!(
+                f,
+                "segment {} (0x{:x} bytes at offset 0x{:x}) extends past the end of the {} byte file",
+                index, size, offset, file_size
+            ),
+            ElfViolation::FileSizeExceedsMemorySize {
+                index,
+                file_size,
+                memory_size,
+            } => write!(
+                f,
+                "segment {} has p_filesz 0x{:x} > p_memsz 0x{:x}",
+                index, file_size, memory_size
+            ),
+            ElfViolation::Alignment { index, align } => write!(
+                f,
+                "segment {} has p_align 0x{:x}, not a power of two",
+                index, align
+            ),
+            ElfViolation::Misaligned {
+                index,
+                address,
+                offset,
+                align,
+            } => write!(
+                f,
+                "segment {} has p_vaddr 0x{:x} and p_offset 0x{:x} not congruent modulo 0x{:x}",
+                index, address, offset, align
+            ),
+            ElfViolation::AddressOverflow { index } => {
+                write!(f, "segment {} wraps around the address space", index)
+            }
+            ElfViolation::Untranslatable { index, address } => write!(
+                f,
+                "segment {} at 0x{:016x} has no load address under the kernel address policy",
+                index, address
+            ),
+            ElfViolation::EntryPointNotExecutable { entry } => write!(
+                f,
+                "entry point 0x{:016x} is not inside an executable segment",
+                entry
+            ),
+            ElfViolation::SectionOutsideFile {
+                index,
+                offset,
+                size,
+                file_size,
+            } => write!(
+                f,
+                "section {} (0x{:x} bytes at offset 0x{:x}) extends past the end of the {} byte file",
+                index, size, offset, file_size
+            ),
+            ElfViolation::SectionLink { index, link } => {
+                write!(f, "section {} links to invalid section {}", index, link)
+            }
+            ElfViolation::SymbolTableLayout { index } => write!(
+                f,
+                "symbol table section {} is misaligned or not a multiple of the entry size",
+                index
+            ),
+        }
+    }
+}
+
+impl BootError {
+    // closest Status, for callers that have to hand one back to the firmware
+    pub fn status(&self) -> Status {
+        match self {
+            BootError::Uefi(status) => *status,
+            BootError::FileNotFound { .. } => Status::NOT_FOUND,
+            BootError::FileRead { status, .. } => *status,
+            BootError::ElfParse { .. } => Status::LOAD_ERROR,
+            BootError::ElfInvalid(_) => Status::LOAD_ERROR,
+            BootError::FileSystem { .. } => Status::VOLUME_CORRUPTED,
+            BootError::Decompression { .. } => Status::LOAD_ERROR,
+            BootError::SegmentOverlap { .. } => Status::LOAD_ERROR,
+            BootError::SegmentOutOfBounds { .. } => Status::LOAD_ERROR,
+            BootError::AllocationFailed { status, .. } => *status,
+            BootError::SymbolMissing { .. } => Status::NOT_FOUND,
+            BootError::VolumeNotFound { .. } => Status::NOT_FOUND,
+            BootError::Panic { .. } => Status::ABORTED,
+            BootError::Context { source, .. } => source.status(),
+        }
+    }
+
+    // innermost error, without any context
+    pub fn root(&self) -> &BootError {
+        match self {
+            BootError::Context { source, .. } => source.root(),
+            _ => self,
+        }
+    }
+
+    // one line per context layer, outermost first and the root cause last
+    pub fn chain(&self) -> vec::Vec<String> {
+        match self {
+            BootError::Context { context, source } => {
+                let mut chain = vec![context.clone()];
+                chain.extend(source.chain());
+                chain
+            }
+            _ => vec![self.to_string()],
+        }
+    }
+}
+
+impl From<uefi::Error> for BootError {
+    fn from(error: uefi::Error) -> Self {
+        BootError::Uefi(error.status())
+    }
+}
+
+impl From<ElfViolation> for BootError {
+    fn from(violation: ElfViolation) -> Self {
+        BootError::ElfInvalid(violation)
+    }
+}
+
+impl From<Status> for BootError {
+    fn from(status: Status) -> Self {
+        BootError::Uefi(status)
+    }
+}
+
+impl fmt::Display for BootError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BootError::Uefi(status) => write!(f, "{:?}", status),
+            BootError::FileNotFound { path } => write!(f, "file not found: {}", path),
+            BootError::FileRead { path, status } => {
+                write!(f, "failed to read {}: {:?}", path, status)
+            }
+            BootError::ElfParse { reason } => write!(f, "invalid ELF: {}", reason),
+            BootError::ElfInvalid(violation) => write!(f, "invalid ELF: {}", violation),
+            BootError::FileSystem { reason } => write!(f, "file system error: {}", reason),
+            BootError::Decompression { format, reason } => {
+                write!(f, "failed to decompress {} image: {}", format, reason)
+            }
+            BootError::SegmentOverlap { first, second } => write!(
+                f,
+                "segments [0x{:016x}, 0x{:016x}) and [0x{:016x}, 0x{:016x}) overlap",
+                first.0, first.1, second.0, second.1
+            ),
+            BootError::SegmentOutOfBounds {
+                address,
+                size,
+                limit,
+            } => write!(
+                f,
+                "segment [0x{:x}, 0x{:x}) exceeds the limit 0x{:x}",
+                address,
+                address.saturating_add(*size),
+                limit
+            ),
+            BootError::AllocationFailed {
+                address: Some(address),
+                pages,
+                memory_type,
+                status,
+            } => write!(
+                f,
+                "failed to allocate {} pages of {:?} at 0x{:016x}: {:?}",
+                pages, memory_type, address, status
+            ),
+            BootError::AllocationFailed {
+                address: None,
+                pages,
+                memory_type,
+                status,
+            } => write!(
+                f,
+                "failed to allocate {} pages of {:?}: {:?}",
+                pages, memory_type, status
+            ),
+            BootError::SymbolMissing { name } => write!(f, "symbol '{}' not found", name),
+            BootError::VolumeNotFound { selector } => {
+                write!(f, "no volume matches {}", selector)
+            }
+            BootError::Panic { message } => write!(f, "panic: {}", message),
+            BootError::Context { context, source } => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+// attach what we were doing to an error on its way up
+pub trait ResultExt<T> {
+    fn context(self, context: &str) -> Result<T, BootError>;
+    fn with_context<F: FnOnce() -> String>(self, context: F) -> Result<T, BootError>;
+}
+
+impl<T, E: Into<BootError>> ResultExt<T> for Result<T, E> {
+    fn context(self, context: &str) -> Result<T, BootError> {
+        self.with_context(|| String::from(context))
+    }
+
+    fn with_context<F: FnOnce() -> String>(self, context: F) -> Result<T, BootError> {
+        self.map_err(|error| BootError::Context {
+            context: context(),
+            source: Box::new(error.into()),
+        })
+    }
+}
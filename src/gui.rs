@@ -1,7 +1,85 @@
 mod bmp;
 pub use bmp::*;
 
+mod image;
+pub use image::*;
+
+#[cfg(feature = "png")]
+mod png;
+#[cfg(feature = "png")]
+pub use png::*;
+
 mod console;
+pub use console::*;
+
+mod graphical_console;
+pub use graphical_console::*;
+
+mod text_console;
+pub use text_console::*;
+
+mod serial_console;
+pub use serial_console::*;
+
+mod fatal_screen;
+pub use fatal_screen::*;
+
+use crate::{config, screen, warn};
+
+extern crate alloc;
+use alloc::vec;
 
 pub const A9N_SPLASH_BMP: &[u8] = include_bytes!("../resources/a9n-project.bmp");
 pub const A9N_LOADER_SPLASH_BMP: &[u8] = include_bytes!("../resources/a9n-loader.bmp");
+#[cfg(feature = "png")]
+pub const A9N_LOADER_SPLASH_PNG: &[u8] = include_bytes!("../resources/a9n-loader.png");
+
+// embedded default splash
+#[cfg(feature = "png")]
+pub const A9N_LOADER_SPLASH: &[u8] = A9N_LOADER_SPLASH_PNG;
+#[cfg(not(feature = "png"))]
+pub const A9N_LOADER_SPLASH: &[u8] = A9N_LOADER_SPLASH_BMP;
+
+pub const BACKGROUND: screen::Color = screen::Color::rgb(0x14, 0x14, 0x14);
+
+static mut SPLASH: Option<vec::Vec<u8>> = None;
+
+// `splash=<path>` replaces the embedded splash with an image from the loader volume
+pub fn load_splash() {
+    let Some(path) = config::current().splash.as_deref() else {
+        return;
+    };
+
+    match crate::loader::read_entire_file(path) {
+        Ok(bytes) if get_image_dimensions(&bytes).is_some() => unsafe { SPLASH = Some(bytes) },
+        Ok(_) => warn!("Unsupported splash image format: {}", path),
+        Err(e) => warn!("Failed to read splash image {}: {}", path, e),
+    }
+}
+
+pub fn splash() -> &'static [u8] {
+    #[allow(static_mut_refs)]
+    unsafe {
+        SPLASH.as_deref().unwrap_or(A9N_LOADER_SPLASH)
+    }
+}
+
+// background plus splash, the console draws below it
+pub fn draw_boot_screen() {
+    let Some(screen) = screen::current_screen() else {
+        return;
+    };
+
+    use crate::screen::Screen;
+    let width = screen.width();
+    let height = screen.height();
+
+    // paint the background first so transparent splash pixels blend over it
+    for y in 0..height {
+        for x in 0..width {
+            screen.draw_pixel(x, y, BACKGROUND);
+        }
+    }
+
+    draw_image(splash(), 0, 0);
+}
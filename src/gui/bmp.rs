@@ -1,185 +1,437 @@
+use crate::gui::Image;
 use crate::screen;
 
-pub struct Bmp<'a> {
+extern crate alloc;
+use alloc::vec;
+
+// compression (biCompression)
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+const BI_RLE4: u32 = 2;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+// info header sizes (biSize)
+const BITMAPCOREHEADER_SIZE: usize = 12;
+const BITMAPINFOHEADER_SIZE: usize = 40;
+
+const FILE_HEADER_SIZE: usize = 14;
+
+// larger than any screen; the header alone must not decide how much we allocate
+pub const MAX_DIMENSION: usize = 16384;
+
+// pixels never written by an RLE stream (delta / early end of bitmap)
+const TRANSPARENT: screen::Color = screen::Color {
+    red: 0,
+    green: 0,
+    blue: 0,
+    alpha: 0,
+};
+
+#[derive(Debug, Clone, Copy)]
+struct BmpHeader {
+    pixel_offset: usize,
+    header_size: usize,
     width: usize,
     height: usize,
-    pixel_width: usize,
-    pixel_raw: &'a [u8],
+    top_down: bool,
+    bits_per_pixel: u16,
+    compression: u32,
+    colors_used: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ChannelMask {
+    mask: u32,
+    shift: u32,
+    bits: u32,
+}
+
+impl ChannelMask {
+    fn new(mask: u32) -> Self {
+        ChannelMask {
+            mask,
+            shift: if mask == 0 { 0 } else { mask.trailing_zeros() },
+            bits: mask.count_ones(),
+        }
+    }
+
+    // scale the masked value up to 8 bits
+    fn extract(&self, value: u32, default: u8) -> u8 {
+        if self.bits == 0 {
+            return default;
+        }
+
+        let max = (1u64 << self.bits) - 1;
+        let raw = ((value & self.mask) >> self.shift) as u64;
+        ((raw * 0xff + max / 2) / max) as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BitFields {
+    red: ChannelMask,
+    green: ChannelMask,
+    blue: ChannelMask,
+    alpha: ChannelMask,
 }
 
-impl Bmp<'_> {
-    pub fn new(raw_bmp: &[u8]) -> Option<Bmp<'_>> {
-        if raw_bmp.len() < 54 {
-            return None; // Not enough data for BMP header
+impl BitFields {
+    fn new(red: u32, green: u32, blue: u32, alpha: u32) -> Self {
+        BitFields {
+            red: ChannelMask::new(red),
+            green: ChannelMask::new(green),
+            blue: ChannelMask::new(blue),
+            alpha: ChannelMask::new(alpha),
         }
+    }
 
-        let dimensions = get_bmp_dimensions(raw_bmp)?;
-        let header = &raw_bmp[0..54];
-        let pixel_raw = &raw_bmp[54..];
+    fn color(&self, value: u32) -> screen::Color {
+        screen::Color {
+            red: self.red.extract(value, 0),
+            green: self.green.extract(value, 0),
+            blue: self.blue.extract(value, 0),
+            alpha: self.alpha.extract(value, 0xff),
+        }
+    }
+}
 
-        let pixel_width = match header[28] {
-            24 => {
-                // 24-bit bmp
-                3
+pub struct Bmp {
+    width: usize,
+    height: usize,
+    // decoded pixels, top-down and row-major regardless of the file layout
+    pixels: vec::Vec<screen::Color>,
+}
+
+impl Bmp {
+    pub fn new(raw_bmp: &[u8]) -> Option<Bmp> {
+        let header = parse_header(raw_bmp)?;
+        if header.width > MAX_DIMENSION || header.height > MAX_DIMENSION {
+            return None;
+        }
+        let pixel_count = header.width.checked_mul(header.height)?;
+
+        let mut pixels = vec::Vec::new();
+        pixels.try_reserve_exact(pixel_count).ok()?;
+        pixels.resize(pixel_count, TRANSPARENT);
+
+        let mut bmp = Bmp {
+            width: header.width,
+            height: header.height,
+            pixels,
+        };
+
+        match (header.compression, header.bits_per_pixel) {
+            (BI_RGB, 1 | 4 | 8) => {
+                let palette = read_palette(raw_bmp, &header)?;
+                bmp.decode_indexed(raw_bmp, &header, &palette)?;
             }
-            32 => {
-                // 32-bit bmp
-                4
+            (BI_RGB, 24) => bmp.decode_rgb24(raw_bmp, &header)?,
+            (BI_RGB, 16) => {
+                let fields = BitFields::new(0x7c00, 0x03e0, 0x001f, 0);
+                bmp.decode_bitfields(raw_bmp, &header, &fields)?;
             }
-            _ => {
-                return None; // Unsupported bit depth
+            (BI_RGB, 32) => {
+                // the fourth byte of BI_RGB is reserved, not alpha
+                let fields = BitFields::new(0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0);
+                bmp.decode_bitfields(raw_bmp, &header, &fields)?;
             }
-        };
+            (BI_BITFIELDS | BI_ALPHABITFIELDS, 16 | 32) => {
+                let fields = read_bitfields(raw_bmp, &header)?;
+                bmp.decode_bitfields(raw_bmp, &header, &fields)?;
+            }
+            (BI_RLE8, 8) | (BI_RLE4, 4) => {
+                let palette = read_palette(raw_bmp, &header)?;
+                bmp.decode_rle(raw_bmp, &header, &palette)?;
+            }
+            _ => return None, // Unsupported bit depth / compression
+        }
 
-        Some(Bmp {
-            width: dimensions.0,
-            height: dimensions.1,
-            pixel_width,
-            pixel_raw,
-        })
+        Some(bmp)
     }
 
-    pub fn width(&self) -> usize {
-        self.width
-    }
+    #[inline]
+    fn set_pixel(&mut self, x: usize, file_row: usize, header: &BmpHeader, color: screen::Color) {
+        if x >= self.width || file_row >= self.height {
+            return;
+        }
 
-    pub fn height(&self) -> usize {
-        self.height
+        let y = if header.top_down {
+            file_row
+        } else {
+            self.height - 1 - file_row
+        };
+        self.pixels[y * self.width + x] = color;
     }
 
-    pub fn pixel_raw(&self) -> &[u8] {
-        self.pixel_raw
+    // rows are padded to 4 bytes
+    fn row<'a>(raw_bmp: &'a [u8], header: &BmpHeader, file_row: usize) -> Option<&'a [u8]> {
+        let stride = (header.width * header.bits_per_pixel as usize).div_ceil(32) * 4;
+        let start = header
+            .pixel_offset
+            .checked_add(file_row.checked_mul(stride)?)?;
+        let used = (header.width * header.bits_per_pixel as usize).div_ceil(8);
+        raw_bmp.get(start..start.checked_add(used)?)
     }
 
-    pub fn pixel_width(&self) -> usize {
-        self.pixel_width
+    fn decode_indexed(
+        &mut self,
+        raw_bmp: &[u8],
+        header: &BmpHeader,
+        palette: &[screen::Color],
+    ) -> Option<()> {
+        let bits = header.bits_per_pixel as usize;
+        let per_byte = 8 / bits;
+        let index_mask = ((1u16 << bits) - 1) as u8;
+
+        for file_row in 0..header.height {
+            let row = Self::row(raw_bmp, header, file_row)?;
+            for x in 0..header.width {
+                // the leftmost pixel lives in the most significant bits
+                let byte = row[x / per_byte];
+                let shift = 8 - bits * (x % per_byte + 1);
+                let index = (byte >> shift) & index_mask;
+                let color = palette.get(index as usize).copied().unwrap_or(TRANSPARENT);
+                self.set_pixel(x, file_row, header, color);
+            }
+        }
+
+        Some(())
     }
 
-    // return pixel with iter
-    pub fn pixel_iter(&self) -> impl Iterator<Item = (usize, usize, screen::Color)> + '_ {
-        self.pixel_raw
-            .chunks(self.pixel_width)
-            .enumerate()
-            .flat_map(move |(i, chunk)| {
-                if chunk.len() < self.pixel_width {
-                    return None; // Skip incomplete pixels
-                }
-                let x = i % self.width;
-                let y = i / self.width;
-                let blue = chunk[0];
-                let green = chunk[1];
-                let red = chunk[2];
-                let alpha = if self.pixel_width == 4 {
-                    chunk[3]
-                } else {
-                    0xff // Default alpha for 24-bit BMP
+    fn decode_rgb24(&mut self, raw_bmp: &[u8], header: &BmpHeader) -> Option<()> {
+        for file_row in 0..header.height {
+            let row = Self::row(raw_bmp, header, file_row)?;
+            for (x, chunk) in row.chunks_exact(3).enumerate() {
+                let color = screen::Color {
+                    red: chunk[2],
+                    green: chunk[1],
+                    blue: chunk[0],
+                    alpha: 0xff,
                 };
-                Some((
-                    x,
-                    y,
-                    screen::Color {
-                        red,
-                        green,
-                        blue,
-                        alpha,
-                    },
-                ))
-            })
-    }
-}
+                self.set_pixel(x, file_row, header, color);
+            }
+        }
 
-pub fn draw_bmp(splash: &[u8], start_x: usize, start_y: usize) {
-    // let mut screen = screen::VgaScreen::new();
-    // screen.clear();
-    let screen = screen::current_screen();
+        Some(())
+    }
 
-    let bmp_dimensions = get_bmp_dimensions(splash).unwrap_or((0, 0));
+    fn decode_bitfields(
+        &mut self,
+        raw_bmp: &[u8],
+        header: &BmpHeader,
+        fields: &BitFields,
+    ) -> Option<()> {
+        let pixel_width = header.bits_per_pixel as usize / 8;
 
-    use crate::screen::Screen;
-    let scale_x = screen.width() as f32 / bmp_dimensions.0 as f32;
-    let scale_y = screen.height() as f32 / bmp_dimensions.1 as f32;
-    let scale = if scale_x < scale_y { scale_x } else { scale_y };
+        for file_row in 0..header.height {
+            let row = Self::row(raw_bmp, header, file_row)?;
+            for (x, chunk) in row.chunks_exact(pixel_width).enumerate() {
+                let value = match pixel_width {
+                    2 => u16::from_le_bytes([chunk[0], chunk[1]]) as u32,
+                    _ => u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]),
+                };
+                self.set_pixel(x, file_row, header, fields.color(value));
+            }
+        }
 
-    let scaled_width = (bmp_dimensions.0 as f32 * scale) as usize;
-    let scaled_height = (bmp_dimensions.1 as f32 * scale) as usize;
+        Some(())
+    }
 
-    // let start_x = (screen.width() - scaled_width) / 2;
-    // let start_y = (screen.height() - scaled_height) / 2;
+    // RLE bitmaps are always stored bottom-up
+    fn decode_rle(
+        &mut self,
+        raw_bmp: &[u8],
+        header: &BmpHeader,
+        palette: &[screen::Color],
+    ) -> Option<()> {
+        let data = raw_bmp.get(header.pixel_offset..)?;
+        let is_rle4 = header.compression == BI_RLE4;
+        let color_of = |index: u8| palette.get(index as usize).copied().unwrap_or(TRANSPARENT);
 
-    let pixel_data = &splash[54..];
+        let mut x = 0usize;
+        let mut file_row = 0usize;
+        let mut position = 0usize;
 
-    for y in 0..scaled_height {
-        for x in 0..scaled_width {
-            let src_x = (x as f32 / scale) as usize;
-            let src_y = (y as f32 / scale) as usize;
+        while position + 1 < data.len() {
+            let count = data[position] as usize;
+            let value = data[position + 1];
+            position += 2;
 
-            let offset = (src_y * bmp_dimensions.0 + src_x) * 3;
-            if offset + 2 >= pixel_data.len() {
-                continue; // Prevent out-of-bounds access
+            if count > 0 {
+                // encoded run
+                for i in 0..count {
+                    let index = if is_rle4 {
+                        if i % 2 == 0 { value >> 4 } else { value & 0x0f }
+                    } else {
+                        value
+                    };
+                    self.set_pixel(x, file_row, header, color_of(index));
+                    x += 1;
+                }
+                continue;
             }
 
-            let blue = pixel_data[offset];
-            let green = pixel_data[offset + 1];
-            let red = pixel_data[offset + 2];
-
-            screen.draw_pixel(
-                start_x + x,
-                start_y + y,
-                screen::Color {
-                    red,
-                    green,
-                    blue,
-                    alpha: 0xff,
-                },
-            );
+            match value {
+                // end of line
+                0 => {
+                    x = 0;
+                    file_row += 1;
+                }
+                // end of bitmap
+                1 => break,
+                // delta
+                2 => {
+                    let delta = data.get(position..position + 2)?;
+                    x += delta[0] as usize;
+                    file_row += delta[1] as usize;
+                    position += 2;
+                }
+                // absolute run, padded to 16 bits
+                length => {
+                    let length = length as usize;
+                    let bytes = if is_rle4 { length.div_ceil(2) } else { length };
+                    let run = data.get(position..position + bytes)?;
+                    for i in 0..length {
+                        let index = if is_rle4 {
+                            let byte = run[i / 2];
+                            if i % 2 == 0 { byte >> 4 } else { byte & 0x0f }
+                        } else {
+                            run[i]
+                        };
+                        self.set_pixel(x, file_row, header, color_of(index));
+                        x += 1;
+                    }
+                    position += bytes.next_multiple_of(2);
+                }
+            }
         }
+
+        Some(())
     }
-    screen.flush_all();
 }
-pub fn draw_bmp_to_screen<Screen>(
-    bmp: &Bmp,
-    screen: &mut Screen,
-    start_x: usize,
-    start_y: usize,
-    scale: f32,
-) where
-    Screen: screen::Screen,
-{
-    for (x, y, color) in bmp.pixel_iter() {
-        let scaled_x = (x as f32 * scale) as usize + start_x;
-        let scaled_y = (y as f32 * scale) as usize + start_y;
-
-        if scaled_x < screen.width() && scaled_y < screen.height() {
-            screen.draw_pixel(scaled_x, scaled_y, color);
+
+impl Image for Bmp {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> screen::Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+fn read_u16(raw: &[u8], offset: usize) -> Option<u16> {
+    raw.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(raw: &[u8], offset: usize) -> Option<u32> {
+    raw.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn parse_header(raw_bmp: &[u8]) -> Option<BmpHeader> {
+    if raw_bmp.get(0..2)? != b"BM" {
+        return None;
+    }
+
+    let pixel_offset = read_u32(raw_bmp, 10)? as usize;
+    let header_size = read_u32(raw_bmp, FILE_HEADER_SIZE)? as usize;
+
+    // OS/2 BITMAPCOREHEADER: 16-bit unsigned dimensions, no compression
+    if header_size == BITMAPCOREHEADER_SIZE {
+        let width = read_u16(raw_bmp, 18)? as usize;
+        let height = read_u16(raw_bmp, 20)? as usize;
+        if width == 0 || height == 0 {
+            return None;
         }
+
+        return Some(BmpHeader {
+            pixel_offset,
+            header_size,
+            width,
+            height,
+            top_down: false,
+            bits_per_pixel: read_u16(raw_bmp, 24)?,
+            compression: BI_RGB,
+            colors_used: 0,
+        });
+    }
+
+    // BITMAPINFOHEADER and its V2/V3/V4/V5 extensions share the same prefix
+    if header_size < BITMAPINFOHEADER_SIZE {
+        return None;
     }
+
+    let width = read_u32(raw_bmp, 18)? as i32;
+    let height = read_u32(raw_bmp, 22)? as i32;
+    if width <= 0 || height == 0 {
+        return None;
+    }
+
+    Some(BmpHeader {
+        pixel_offset,
+        header_size,
+        width: width as usize,
+        height: height.unsigned_abs() as usize,
+        top_down: height < 0,
+        bits_per_pixel: read_u16(raw_bmp, 28)?,
+        compression: read_u32(raw_bmp, 30)?,
+        colors_used: read_u32(raw_bmp, 46)? as usize,
+    })
+}
+
+fn read_palette(raw_bmp: &[u8], header: &BmpHeader) -> Option<vec::Vec<screen::Color>> {
+    let max_colors = 1usize << header.bits_per_pixel;
+    let count = match header.colors_used {
+        0 => max_colors,
+        used => used.min(max_colors),
+    };
+
+    // RGBTRIPLE for the core header, RGBQUAD otherwise
+    let entry_size = if header.header_size == BITMAPCOREHEADER_SIZE {
+        3
+    } else {
+        4
+    };
+    let start = FILE_HEADER_SIZE + header.header_size;
+    let table = raw_bmp.get(start..start + count * entry_size)?;
+
+    Some(
+        table
+            .chunks_exact(entry_size)
+            .map(|entry| screen::Color {
+                red: entry[2],
+                green: entry[1],
+                blue: entry[0],
+                alpha: 0xff,
+            })
+            .collect(),
+    )
+}
+
+fn read_bitfields(raw_bmp: &[u8], header: &BmpHeader) -> Option<BitFields> {
+    // V2+ headers carry the masks inside the header, BITMAPINFOHEADER appends them after it
+    let masks_offset = FILE_HEADER_SIZE + BITMAPINFOHEADER_SIZE;
+    let red = read_u32(raw_bmp, masks_offset)?;
+    let green = read_u32(raw_bmp, masks_offset + 4)?;
+    let blue = read_u32(raw_bmp, masks_offset + 8)?;
+    let has_alpha_mask =
+        header.header_size >= BITMAPINFOHEADER_SIZE + 16 || header.compression == BI_ALPHABITFIELDS;
+    let alpha = if has_alpha_mask {
+        read_u32(raw_bmp, masks_offset + 12)?
+    } else {
+        0
+    };
+
+    Some(BitFields::new(red, green, blue, alpha))
 }
 
 pub fn get_bmp_dimensions(header: &[u8]) -> Option<(usize, usize)> {
-    if header.len() < 18 {
-        return None; // Not enough data for dimensions
-    }
-
-    // width: 18 +4
-    let width_bytes = &header[18..22];
-    // height: 22 +4
-    let height_bytes = &header[22..26];
-
-    let width = u32::from_le_bytes([
-        width_bytes[0],
-        width_bytes[1],
-        width_bytes[2],
-        width_bytes[3],
-    ]) as usize;
-
-    // height is stored as a signed integer in BMP files, but we treat it as unsigned here.
-    let height_raw = i32::from_le_bytes([
-        height_bytes[0],
-        height_bytes[1],
-        height_bytes[2],
-        height_bytes[3],
-    ]);
-    let height = height_raw.unsigned_abs() as usize;
-
-    Some((width, height))
+    parse_header(header).map(|header| (header.width, header.height))
 }
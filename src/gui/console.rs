@@ -1,13 +1,27 @@
 use crate::util::*;
 
+// position in character cells, (0, 0) is the top-left corner of the console
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct Cursor {
     pub x: usize,
     pub y: usize,
 }
 
 pub trait Console {
-    fn write_str(&self, s: &str) -> BootResult<()>;
-    fn clear(&self) -> BootResult<()>;
+    // short name used in config keys, e.g. `log.serial=debug`
+    fn name(&self) -> &'static str;
+
+    fn write_str(&mut self, s: &str) -> BootResult<()>;
+    fn clear(&mut self) -> BootResult<()>;
     fn cursor(&self) -> BootResult<Cursor>;
-    fn set_cursor(&self, cursor: &Cursor) -> BootResult<()>;
+    fn set_cursor(&mut self, cursor: &Cursor) -> BootResult<()>;
+
+    // (columns, rows)
+    fn size(&self) -> BootResult<(usize, usize)>;
+
+    // consoles with a scrollback page through it (positive rows: back in history),
+    // returns false if the console has none
+    fn scroll_view(&mut self, _rows: isize) -> bool {
+        false
+    }
 }
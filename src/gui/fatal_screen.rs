@@ -0,0 +1,249 @@
+use crate::input::{self, Key};
+use crate::loader::chainload;
+use crate::screen::{self, Screen};
+use crate::util::*;
+use crate::{log, print, warn};
+
+extern crate alloc;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+
+use embedded_graphics::{
+    mono_font::{
+        MonoFont, MonoTextStyle,
+        ascii::{FONT_6X12, FONT_10X20},
+    },
+    pixelcolor::Rgb888,
+    prelude::*,
+    text::{Baseline, Text},
+};
+use uefi::runtime::{self, ResetType, VariableAttributes, VariableVendor};
+use uefi::{CStr16, cstr16};
+
+const FATAL_BACKGROUND: screen::Color = screen::Color::rgb(0x3a, 0x0c, 0x0c);
+const MARGIN: usize = 20;
+const LOG_TAIL_LINES: usize = 16;
+
+// tried in order by the shell option
+const SHELL_PATHS: [&str; 3] = [
+    r"\EFI\tools\shellx64.efi",
+    r"\EFI\BOOT\shellx64.efi",
+    r"\shellx64.efi",
+];
+
+const OS_INDICATIONS: &CStr16 = cstr16!("OsIndications");
+const OS_INDICATIONS_SUPPORTED: &CStr16 = cstr16!("OsIndicationsSupported");
+const EFI_OS_INDICATIONS_BOOT_TO_FW_UI: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatalAction {
+    Retry,
+    // back to the firmware with a failure status
+    Exit,
+}
+
+// shows the error until the user picks something; reboot and shutdown never return.
+// `can_retry` is false for panics, there is nothing left to retry from
+pub fn fatal_screen(error: &BootError, can_retry: bool) -> FatalAction {
+    input::flush_keys();
+    let mut status = None;
+
+    loop {
+        draw(error, can_retry, status.as_deref());
+
+        status = match input::wait_key() {
+            Key::Char('r' | 'R') if can_retry => return FatalAction::Retry,
+            Key::Escape => return FatalAction::Exit,
+            Key::Char('b' | 'B') => runtime::reset(ResetType::COLD, error.status(), None),
+            Key::Char('s' | 'S') => runtime::reset(ResetType::SHUTDOWN, error.status(), None),
+            Key::Char('f' | 'F') => Some(reboot_to_firmware_setup(error)),
+            Key::Char('u' | 'U') => Some(run_shell()),
+            _ => status,
+        };
+    }
+}
+
+fn options(can_retry: bool) -> String {
+    format!(
+        "{}[B] Reboot  [S] Shut down  [F] Firmware setup  [U] UEFI shell  [Esc] Exit",
+        if can_retry { "[R] Retry  " } else { "" }
+    )
+}
+
+// oldest first
+fn log_tail() -> vec::Vec<String> {
+    let contents = log::memory_log_contents();
+    let text = String::from_utf8_lossy(&contents);
+    let lines = text.lines().collect::<vec::Vec<_>>();
+    lines[lines.len().saturating_sub(LOG_TAIL_LINES)..]
+        .iter()
+        .map(|line| String::from(*line))
+        .collect()
+}
+
+fn draw(error: &BootError, can_retry: bool, status: Option<&str>) {
+    let chain = error.chain();
+    let tail = log_tail();
+
+    if let Some(screen) = screen::current_screen() {
+        draw_to_screen(screen, &chain, &tail, can_retry, status);
+    }
+
+    // serial and ConOut get the same content as plain text; the GOP console is covered by the
+    // screen above
+    let mut text = String::from("\n*** a9nloader: boot failed ***\n");
+    for (depth, line) in chain.iter().enumerate() {
+        text += &format!("{:width$}{}\n", "", line, width = depth * 2);
+    }
+    text += &format!("\n{}\n", options(can_retry));
+    if let Some(status) = status {
+        text += &format!("{}\n", status);
+    }
+    for (index, name) in print::console_names().enumerate() {
+        if name != "gop" {
+            print::write_console(index, &text);
+        }
+    }
+}
+
+fn draw_to_screen(
+    screen: &mut screen::VgaScreen,
+    chain: &[String],
+    tail: &[String],
+    can_retry: bool,
+    status: Option<&str>,
+) {
+    let (width, height) = (screen.width(), screen.height());
+    screen.fill_rect(0, 0, width, height, FATAL_BACKGROUND);
+
+    let mut y = MARGIN;
+    let mut line = |screen: &mut screen::VgaScreen, font, color, text: &str, indent: usize| {
+        draw_text(screen, font, color, MARGIN + indent, y, text);
+        y += font.character_size.height as usize + 2;
+    };
+
+    line(
+        screen,
+        &FONT_10X20,
+        Rgb888::WHITE,
+        "a9nloader: boot failed",
+        0,
+    );
+    for (depth, text) in chain.iter().enumerate() {
+        line(
+            screen,
+            &FONT_6X12,
+            Rgb888::new(255, 200, 200),
+            text,
+            depth * 12,
+        );
+    }
+
+    line(screen, &FONT_6X12, Rgb888::WHITE, "", 0);
+    line(
+        screen,
+        &FONT_6X12,
+        Rgb888::new(200, 200, 200),
+        "Last log lines:",
+        0,
+    );
+    for text in tail {
+        line(screen, &FONT_6X12, Rgb888::new(170, 170, 170), text, 12);
+    }
+
+    line(screen, &FONT_6X12, Rgb888::WHITE, "", 0);
+    line(screen, &FONT_6X12, Rgb888::YELLOW, &options(can_retry), 0);
+    if let Some(status) = status {
+        line(screen, &FONT_6X12, Rgb888::WHITE, status, 0);
+    }
+
+    screen.flush_all();
+}
+
+fn draw_text(
+    screen: &mut screen::VgaScreen,
+    font: &MonoFont,
+    color: Rgb888,
+    x: usize,
+    y: usize,
+    text: &str,
+) {
+    // cut at the right edge instead of running off screen
+    let cell = (font.character_size.width + font.character_spacing) as usize;
+    let columns = screen.width().saturating_sub(x + MARGIN) / cell;
+    let end = text
+        .char_indices()
+        .nth(columns)
+        .map(|(index, _)| index)
+        .unwrap_or(text.len());
+
+    let _ = Text::with_baseline(
+        &text[..end],
+        Point::new(x as i32, y as i32),
+        MonoTextStyle::new(font, color),
+        Baseline::Top,
+    )
+    .draw(screen);
+}
+
+// only returns if the firmware can't do it
+fn reboot_to_firmware_setup(error: &BootError) -> String {
+    let mut buffer = [0u8; 8];
+    let supported = runtime::get_variable(
+        OS_INDICATIONS_SUPPORTED,
+        &VariableVendor::GLOBAL_VARIABLE,
+        &mut buffer,
+    )
+    .ok()
+    .and_then(|(data, _)| data.try_into().ok())
+    .map(u64::from_le_bytes)
+    .unwrap_or(0);
+
+    if supported & EFI_OS_INDICATIONS_BOOT_TO_FW_UI == 0 {
+        return String::from("This firmware can't boot into its setup from the OS");
+    }
+
+    // keep whatever else is already requested
+    let mut buffer = [0u8; 8];
+    let indications = runtime::get_variable(
+        OS_INDICATIONS,
+        &VariableVendor::GLOBAL_VARIABLE,
+        &mut buffer,
+    )
+    .ok()
+    .and_then(|(data, _)| data.try_into().ok())
+    .map(u64::from_le_bytes)
+    .unwrap_or(0);
+
+    let result = runtime::set_variable(
+        OS_INDICATIONS,
+        &VariableVendor::GLOBAL_VARIABLE,
+        VariableAttributes::NON_VOLATILE
+            | VariableAttributes::BOOTSERVICE_ACCESS
+            | VariableAttributes::RUNTIME_ACCESS,
+        &(indications | EFI_OS_INDICATIONS_BOOT_TO_FW_UI).to_le_bytes(),
+    );
+
+    match result {
+        Ok(()) => runtime::reset(ResetType::COLD, error.status(), None),
+        Err(e) => format!("Failed to request firmware setup: {}", e),
+    }
+}
+
+// run a UEFI shell from the loader volume and come back here when it exits
+fn run_shell() -> String {
+    for path in SHELL_PATHS {
+        match chainload(path, None) {
+            Ok(()) => return String::from("Returned from the UEFI shell"),
+            // not there, try the next one
+            Err(e) if e.status() == uefi::Status::NOT_FOUND => continue,
+            Err(e) => {
+                warn!("Failed to start {}: {}", path, e);
+                return format!("Failed to start {}: {}", path, e);
+            }
+        }
+    }
+
+    format!("No UEFI shell found (tried {})", SHELL_PATHS.join(", "))
+}
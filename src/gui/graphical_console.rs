@@ -0,0 +1,481 @@
+use crate::gui::{self, Console, Cursor};
+use crate::screen;
+use crate::util::*;
+
+extern crate alloc;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec;
+
+use embedded_graphics::{
+    mono_font::{MonoFont, MonoTextStyle},
+    pixelcolor::Rgb888,
+    prelude::*,
+    text::{Baseline, Text},
+};
+
+const CONSOLE_WIDTH_OFFSET: usize = 10;
+const TAB_WIDTH: usize = 8;
+
+const DEFAULT_FOREGROUND: Rgb888 = Rgb888::WHITE;
+
+// ESC [ 30-37 m / 90-97 m (same palette embedded-text used to render our log colors)
+pub const ANSI_COLORS: [Rgb888; 16] = [
+    Rgb888::new(12, 12, 12),
+    Rgb888::new(197, 15, 31),
+    Rgb888::new(19, 161, 14),
+    Rgb888::new(193, 156, 0),
+    Rgb888::new(0, 55, 218),
+    Rgb888::new(136, 23, 152),
+    Rgb888::new(58, 150, 221),
+    Rgb888::new(204, 204, 204),
+    Rgb888::new(118, 118, 118),
+    Rgb888::new(231, 72, 86),
+    Rgb888::new(22, 198, 12),
+    Rgb888::new(249, 241, 165),
+    Rgb888::new(59, 120, 255),
+    Rgb888::new(180, 0, 158),
+    Rgb888::new(97, 214, 214),
+    Rgb888::new(242, 242, 242),
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Cell {
+    character: char,
+    foreground: Rgb888,
+    background: Option<Rgb888>,
+}
+
+enum EscapeState {
+    Normal,
+    // got ESC
+    Escape,
+    // got ESC [, collecting parameters
+    Csi(String),
+}
+
+// text console drawn with a mono font below the splash, on the global VgaScreen
+pub struct GraphicalConsole {
+    font: &'static MonoFont<'static>,
+    foreground: Rgb888,
+    background: Option<Rgb888>,
+    escape: EscapeState,
+    // cursor, in cells relative to the console region
+    column: usize,
+    row: usize,
+    // every (possibly wrapped) row still kept, oldest first; the live view starts at `top_index`
+    rows: VecDeque<vec::Vec<Cell>>,
+    top_index: usize,
+    scrollback_limit: usize,
+    // rows scrolled back from the live view, 0 while following the output
+    view_offset: usize,
+    // y of the console region; the splash and screen are set up before any console
+    top: usize,
+    // visible rows drawn since the last flush, [start, end)
+    dirty_rows: Option<(usize, usize)>,
+}
+
+// the splash is drawn scaled to the screen width, the console starts below it
+fn calculate_console_height_offset() -> usize {
+    let (w, h) = gui::get_image_dimensions(gui::splash()).unwrap_or((0, 0));
+    let scaled_height = match screen::current_screen() {
+        Some(screen) if w > 0 && h > 0 => {
+            use crate::screen::Screen;
+            let scale_x = screen.width() as f32 / w as f32;
+            let scale_y = screen.height() as f32 / h as f32;
+            (h as f32 * if scale_x < scale_y { scale_x } else { scale_y }) as usize
+        }
+        _ => h,
+    };
+    scaled_height + 10
+}
+
+fn to_color(color: Rgb888) -> screen::Color {
+    screen::Color::rgb(color.r(), color.g(), color.b())
+}
+
+impl GraphicalConsole {
+    pub fn new(font: &'static MonoFont<'static>, scrollback_limit: usize) -> Self {
+        let mut rows = VecDeque::with_capacity(scrollback_limit.min(1024));
+        rows.push_back(vec::Vec::new());
+
+        let mut console = GraphicalConsole {
+            font,
+            foreground: DEFAULT_FOREGROUND,
+            background: None,
+            escape: EscapeState::Normal,
+            column: 0,
+            row: 0,
+            rows,
+            top_index: 0,
+            scrollback_limit,
+            view_offset: 0,
+            top: calculate_console_height_offset(),
+            dirty_rows: None,
+        };
+
+        // never keep less than a screenful, even with `scrollback=0`
+        if let Some(screen) = screen::current_screen() {
+            console.scrollback_limit = scrollback_limit.max(console.visible_rows(screen));
+        }
+        console
+    }
+
+    fn cell_width(&self) -> usize {
+        (self.font.character_size.width + self.font.character_spacing) as usize
+    }
+
+    fn line_height(&self) -> usize {
+        self.font.character_size.height as usize
+    }
+
+    fn top(&self) -> usize {
+        self.top
+    }
+
+    fn visible_rows(&self, screen: &screen::VgaScreen) -> usize {
+        let height = (screen.size().height as usize).saturating_sub(self.top());
+        (height / self.line_height()).max(1)
+    }
+
+    fn columns(&self, screen: &screen::VgaScreen) -> usize {
+        let width = (screen.size().width as usize).saturating_sub(2 * CONSOLE_WIDTH_OFFSET);
+        (width / self.cell_width()).max(1)
+    }
+
+    // height of the scroll region, the remainder below the last full row is left alone
+    fn region_height(&self, screen: &screen::VgaScreen) -> usize {
+        self.visible_rows(screen) * self.line_height()
+    }
+
+    fn draw_cell(
+        &self,
+        screen: &mut screen::VgaScreen,
+        column: usize,
+        row: usize,
+        cell: Option<Cell>,
+    ) {
+        let x = CONSOLE_WIDTH_OFFSET + column * self.cell_width();
+        let y = self.top() + row * self.line_height();
+
+        let background = cell
+            .and_then(|cell| cell.background)
+            .map(to_color)
+            .unwrap_or(gui::BACKGROUND);
+        screen::Screen::fill_rect(
+            screen,
+            x,
+            y,
+            self.cell_width(),
+            self.line_height(),
+            background,
+        );
+
+        if let Some(cell) = cell.filter(|cell| cell.character != ' ') {
+            let mut buffer = [0u8; 4];
+            let style = MonoTextStyle::new(self.font, cell.foreground);
+            let _ = Text::with_baseline(
+                cell.character.encode_utf8(&mut buffer),
+                Point::new(x as i32, y as i32),
+                style,
+                Baseline::Top,
+            )
+            .draw(screen);
+        }
+    }
+
+    fn draw_row(&self, screen: &mut screen::VgaScreen, row: usize, cells: &[Cell]) {
+        let y = self.top() + row * self.line_height();
+        let width = screen.size().width as usize;
+        screen::Screen::fill_rect(screen, 0, y, width, self.line_height(), gui::BACKGROUND);
+
+        for (column, cell) in cells.iter().enumerate() {
+            self.draw_cell(screen, column, row, Some(*cell));
+        }
+    }
+
+    fn flush_region(&mut self, screen: &mut screen::VgaScreen) {
+        let width = screen.size().width as usize;
+        let height = self.region_height(screen);
+        screen::Screen::flush_rect(screen, 0, self.top(), width, height);
+        self.dirty_rows = None;
+    }
+
+    fn mark_dirty(&mut self, start: usize, end: usize) {
+        self.dirty_rows = Some(match self.dirty_rows {
+            Some((dirty_start, dirty_end)) => (dirty_start.min(start), dirty_end.max(end)),
+            None => (start, end),
+        });
+    }
+
+    // only the rows written since the last flush go to the frame buffer
+    fn flush_dirty_rows(&mut self, screen: &mut screen::VgaScreen) {
+        if let Some((start, end)) = self.dirty_rows.take() {
+            let width = screen.size().width as usize;
+            screen::Screen::flush_rect(
+                screen,
+                0,
+                self.top() + start * self.line_height(),
+                width,
+                (end - start) * self.line_height(),
+            );
+        }
+    }
+
+    fn current_row(&mut self) -> &mut vec::Vec<Cell> {
+        let index = self.top_index + self.row;
+        while self.rows.len() <= index {
+            self.rows.push_back(vec::Vec::new());
+        }
+        &mut self.rows[index]
+    }
+
+    // drop history beyond the limit, never anything still on screen
+    fn trim_scrollback(&mut self) {
+        while self.top_index > self.scrollback_limit {
+            self.rows.pop_front();
+            self.top_index -= 1;
+        }
+    }
+
+    // move to the start of the next row
+    fn new_line(&mut self, screen: &mut screen::VgaScreen) {
+        self.column = 0;
+
+        if self.row + 1 < self.visible_rows(screen) {
+            self.row += 1;
+        } else {
+            // scroll the console region up by one row, the splash stays in place
+            screen::Screen::scroll_up(
+                screen,
+                self.top(),
+                self.region_height(screen),
+                self.line_height(),
+                gui::BACKGROUND,
+            );
+            self.top_index += 1;
+            self.trim_scrollback();
+            self.mark_dirty(0, self.visible_rows(screen));
+        }
+
+        self.current_row();
+    }
+
+    fn put_char(&mut self, screen: &mut screen::VgaScreen, character: char) {
+        if self.column >= self.columns(screen) {
+            self.new_line(screen);
+        }
+
+        let cell = Cell {
+            character,
+            foreground: self.foreground,
+            background: self.background,
+        };
+        let blank = Cell {
+            character: ' ',
+            foreground: self.foreground,
+            background: None,
+        };
+
+        // writing after a \r, backspace or set_cursor overwrites, past the end appends
+        let column = self.column;
+        let row = self.current_row();
+        if column < row.len() {
+            row[column] = cell;
+        } else {
+            row.resize(column, blank);
+            row.push(cell);
+        }
+
+        self.draw_cell(screen, self.column, self.row, Some(cell));
+        self.mark_dirty(self.row, self.row + 1);
+        self.column += 1;
+    }
+
+    fn write_char(&mut self, screen: &mut screen::VgaScreen, character: char) {
+        match &mut self.escape {
+            EscapeState::Escape => {
+                self.escape = if character == '[' {
+                    EscapeState::Csi(String::new())
+                } else {
+                    EscapeState::Normal
+                };
+                return;
+            }
+            EscapeState::Csi(parameters) => {
+                if character.is_ascii_digit() || character == ';' {
+                    parameters.push(character);
+                } else {
+                    let parameters = core::mem::take(parameters);
+                    self.escape = EscapeState::Normal;
+                    if character == 'm' {
+                        self.select_graphic_rendition(&parameters);
+                    }
+                }
+                return;
+            }
+            EscapeState::Normal => {}
+        }
+
+        match character {
+            '\x1b' => self.escape = EscapeState::Escape,
+            '\n' => self.new_line(screen),
+            '\r' => self.column = 0,
+            '\t' => {
+                let next_stop = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                let columns = self.columns(screen);
+                while self.column < next_stop.min(columns) {
+                    self.put_char(screen, ' ');
+                }
+            }
+            '\x08' => self.column = self.column.saturating_sub(1),
+            character if character.is_control() => {}
+            character => self.put_char(screen, character),
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, parameters: &str) {
+        for code in parameters.split(';') {
+            match code.parse::<usize>().unwrap_or(0) {
+                0 => {
+                    self.foreground = DEFAULT_FOREGROUND;
+                    self.background = None;
+                }
+                code @ 30..=37 => self.foreground = ANSI_COLORS[code - 30],
+                39 => self.foreground = DEFAULT_FOREGROUND,
+                code @ 40..=47 => self.background = Some(ANSI_COLORS[code - 40]),
+                49 => self.background = None,
+                code @ 90..=97 => self.foreground = ANSI_COLORS[code - 90 + 8],
+                code @ 100..=107 => self.background = Some(ANSI_COLORS[code - 100 + 8]),
+                _ => {}
+            }
+        }
+    }
+
+    // repaint the console region from the row ring at the current view offset
+    fn redraw(&mut self, screen: &mut screen::VgaScreen) {
+        let rows = self.visible_rows(screen);
+        let width = screen.size().width as usize;
+        screen::Screen::fill_rect(
+            screen,
+            0,
+            self.top(),
+            width,
+            self.region_height(screen),
+            gui::BACKGROUND,
+        );
+
+        let start = self.top_index - self.view_offset;
+        let end = (start + rows).min(self.rows.len());
+        for (row, index) in (start..end).enumerate() {
+            self.draw_row(screen, row, &self.rows[index]);
+        }
+
+        if self.view_offset > 0 {
+            let indicator = alloc::format!(
+                "-- scrollback: {} rows up (PageUp/PageDown) --",
+                self.view_offset
+            );
+            let cells: vec::Vec<Cell> = indicator
+                .chars()
+                .map(|character| Cell {
+                    character,
+                    foreground: ANSI_COLORS[3],
+                    background: None,
+                })
+                .collect();
+            self.draw_row(screen, rows - 1, &cells);
+        }
+
+        self.flush_region(screen);
+    }
+
+    fn follow_output(&mut self, screen: &mut screen::VgaScreen) {
+        if self.view_offset != 0 {
+            self.view_offset = 0;
+            self.redraw(screen);
+        }
+    }
+}
+
+impl Console for GraphicalConsole {
+    fn name(&self) -> &'static str {
+        "gop"
+    }
+
+    fn write_str(&mut self, s: &str) -> BootResult<()> {
+        let Some(screen) = screen::current_screen() else {
+            return Ok(());
+        };
+
+        // new output always jumps back to the live view
+        self.follow_output(screen);
+
+        for character in s.chars() {
+            self.write_char(screen, character);
+        }
+        self.flush_dirty_rows(screen);
+
+        Ok(())
+    }
+
+    // the cleared rows stay in the scrollback
+    fn clear(&mut self) -> BootResult<()> {
+        let Some(screen) = screen::current_screen() else {
+            return Ok(());
+        };
+
+        self.view_offset = 0;
+        self.top_index = self.rows.len();
+        self.row = 0;
+        self.column = 0;
+        self.current_row();
+        self.trim_scrollback();
+        self.redraw(screen);
+
+        Ok(())
+    }
+
+    fn cursor(&self) -> BootResult<Cursor> {
+        Ok(Cursor {
+            x: self.column,
+            y: self.row,
+        })
+    }
+
+    fn set_cursor(&mut self, cursor: &Cursor) -> BootResult<()> {
+        let Some(screen) = screen::current_screen() else {
+            return Ok(());
+        };
+
+        self.follow_output(screen);
+        self.column = cursor.x.min(self.columns(screen) - 1);
+        self.row = cursor.y.min(self.visible_rows(screen) - 1);
+        self.current_row();
+
+        Ok(())
+    }
+
+    fn size(&self) -> BootResult<(usize, usize)> {
+        screen::current_screen()
+            .map(|screen| (self.columns(screen), self.visible_rows(screen)))
+            .ok_or(uefi_error(uefi::Status::NOT_READY))
+    }
+
+    fn scroll_view(&mut self, rows: isize) -> bool {
+        let Some(screen) = screen::current_screen() else {
+            return false;
+        };
+
+        let offset = self
+            .view_offset
+            .saturating_add_signed(rows)
+            .min(self.top_index);
+
+        if offset != self.view_offset {
+            self.view_offset = offset;
+            self.redraw(screen);
+        }
+        true
+    }
+}
@@ -0,0 +1,88 @@
+use crate::gui::Bmp;
+#[cfg(feature = "png")]
+use crate::gui::Png;
+use crate::screen;
+
+extern crate alloc;
+use alloc::boxed::Box;
+
+// decoded image, pixels are addressed top-down
+pub trait Image {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+    fn pixel(&self, x: usize, y: usize) -> screen::Color;
+}
+
+// pick the decoder from the file magic
+pub fn decode_image(raw: &[u8]) -> Option<Box<dyn Image>> {
+    if raw.starts_with(b"BM") {
+        return Bmp::new(raw).map(|bmp| Box::new(bmp) as Box<dyn Image>);
+    }
+
+    #[cfg(feature = "png")]
+    if raw.starts_with(crate::gui::PNG_SIGNATURE) {
+        return Png::new(raw).map(|png| Box::new(png) as Box<dyn Image>);
+    }
+
+    None
+}
+
+// header-only, without decoding the pixels
+pub fn get_image_dimensions(raw: &[u8]) -> Option<(usize, usize)> {
+    #[cfg(feature = "png")]
+    if raw.starts_with(crate::gui::PNG_SIGNATURE) {
+        return crate::gui::get_png_dimensions(raw);
+    }
+
+    crate::gui::get_bmp_dimensions(raw)
+}
+
+// draw `raw` scaled to fit the screen
+pub fn draw_image(raw: &[u8], start_x: usize, start_y: usize) {
+    let Some(screen) = screen::current_screen() else {
+        return;
+    };
+    let Some(image) = decode_image(raw) else {
+        return;
+    };
+
+    use crate::screen::Screen;
+    let scale_x = screen.width() as f32 / image.width() as f32;
+    let scale_y = screen.height() as f32 / image.height() as f32;
+    let scale = if scale_x < scale_y { scale_x } else { scale_y };
+
+    let scaled_width = (image.width() as f32 * scale) as usize;
+    let scaled_height = (image.height() as f32 * scale) as usize;
+
+    for y in 0..scaled_height {
+        for x in 0..scaled_width {
+            let src_x = ((x as f32 / scale) as usize).min(image.width() - 1);
+            let src_y = ((y as f32 / scale) as usize).min(image.height() - 1);
+
+            screen.blend_pixel(start_x + x, start_y + y, image.pixel(src_x, src_y));
+        }
+    }
+    screen.flush_all();
+}
+
+pub fn draw_image_to_screen<I, Screen>(
+    image: &I,
+    screen: &mut Screen,
+    start_x: usize,
+    start_y: usize,
+    scale: f32,
+) where
+    I: Image + ?Sized,
+    Screen: screen::Screen,
+{
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            let scaled_x = (x as f32 * scale) as usize + start_x;
+            let scaled_y = (y as f32 * scale) as usize + start_y;
+
+            if scaled_x < screen.width() && scaled_y < screen.height() {
+                screen.blend_pixel(scaled_x, scaled_y, image.pixel(x, y));
+            }
+        }
+    }
+}
@@ -0,0 +1,426 @@
+use crate::gui::{Image, MAX_DIMENSION};
+use crate::screen;
+
+extern crate alloc;
+use alloc::vec;
+
+pub const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+// color type (IHDR)
+const COLOR_GRAY: u8 = 0;
+const COLOR_RGB: u8 = 2;
+const COLOR_PALETTE: u8 = 3;
+const COLOR_GRAY_ALPHA: u8 = 4;
+const COLOR_RGBA: u8 = 6;
+
+// Adam7 passes: (x start, y start, x step, y step)
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+#[derive(Debug, Clone, Copy)]
+struct PngHeader {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl PngHeader {
+    fn channels(&self) -> usize {
+        match self.color_type {
+            COLOR_GRAY | COLOR_PALETTE => 1,
+            COLOR_GRAY_ALPHA => 2,
+            COLOR_RGB => 3,
+            _ => 4,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    // filter distance in bytes, at least one
+    fn filter_stride(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    fn row_bytes(&self, width: usize) -> usize {
+        (width * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    // exact length of the inflated data: every row of every pass plus its filter byte
+    fn filtered_size(&self) -> usize {
+        let passes: &[(usize, usize, usize, usize)] = if self.interlaced {
+            &ADAM7
+        } else {
+            &[(0, 0, 1, 1)]
+        };
+        passes
+            .iter()
+            .map(|(x_start, y_start, x_step, y_step)| {
+                let width = self.width.saturating_sub(*x_start).div_ceil(*x_step);
+                let height = self.height.saturating_sub(*y_start).div_ceil(*y_step);
+                if width == 0 || height == 0 {
+                    0
+                } else {
+                    (self.row_bytes(width) + 1) * height
+                }
+            })
+            .sum()
+    }
+
+    fn is_valid(&self) -> bool {
+        matches!(
+            (self.color_type, self.bit_depth),
+            (COLOR_GRAY, 1 | 2 | 4 | 8 | 16)
+                | (COLOR_PALETTE, 1 | 2 | 4 | 8)
+                | (COLOR_RGB | COLOR_GRAY_ALPHA | COLOR_RGBA, 8 | 16)
+        )
+    }
+}
+
+// tRNS: a single transparent color for gray / rgb, per-entry alpha for palettes
+enum Transparency {
+    None,
+    Gray(u16),
+    Rgb(u16, u16, u16),
+}
+
+pub struct Png {
+    width: usize,
+    height: usize,
+    // decoded pixels, top-down and row-major
+    pixels: vec::Vec<screen::Color>,
+}
+
+impl Png {
+    pub fn new(raw_png: &[u8]) -> Option<Png> {
+        if !raw_png.starts_with(PNG_SIGNATURE) {
+            return None;
+        }
+
+        let mut header = None;
+        let mut palette = vec::Vec::new();
+        let mut transparency = Transparency::None;
+        let mut compressed = vec::Vec::new();
+
+        for (chunk_type, data) in ChunkIter::new(&raw_png[PNG_SIGNATURE.len()..]) {
+            match chunk_type {
+                b"IHDR" => header = Some(parse_header(data)?),
+                b"PLTE" => {
+                    palette = data
+                        .chunks_exact(3)
+                        .map(|rgb| screen::Color {
+                            red: rgb[0],
+                            green: rgb[1],
+                            blue: rgb[2],
+                            alpha: 0xff,
+                        })
+                        .collect();
+                }
+                b"tRNS" => match header?.color_type {
+                    COLOR_PALETTE => {
+                        for (entry, alpha) in palette.iter_mut().zip(data.iter()) {
+                            entry.alpha = *alpha;
+                        }
+                    }
+                    COLOR_GRAY if data.len() >= 2 => {
+                        transparency = Transparency::Gray(read_u16(data, 0)?);
+                    }
+                    COLOR_RGB if data.len() >= 6 => {
+                        transparency = Transparency::Rgb(
+                            read_u16(data, 0)?,
+                            read_u16(data, 2)?,
+                            read_u16(data, 4)?,
+                        );
+                    }
+                    _ => {}
+                },
+                b"IDAT" => compressed.extend_from_slice(data),
+                b"IEND" => break,
+                _ => {} // ancillary chunks are ignored
+            }
+        }
+
+        let header = header?;
+        if header.color_type == COLOR_PALETTE && palette.is_empty() {
+            return None;
+        }
+        // the same bound as BMP, a splash from the ESP must not decide how much we allocate
+        if header.width > MAX_DIMENSION || header.height > MAX_DIMENSION {
+            return None;
+        }
+
+        let filtered = miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(
+            &compressed,
+            header.filtered_size(),
+        )
+        .ok()?;
+
+        let pixel_count = header.width.checked_mul(header.height)?;
+        let mut pixels = vec::Vec::new();
+        pixels.try_reserve_exact(pixel_count).ok()?;
+        pixels.resize(pixel_count, screen::Color::default());
+
+        let mut png = Png {
+            width: header.width,
+            height: header.height,
+            pixels,
+        };
+
+        let decoder = PixelDecoder {
+            header: &header,
+            palette: &palette,
+            transparency: &transparency,
+        };
+
+        if header.interlaced {
+            let mut offset = 0;
+            for (x_start, y_start, x_step, y_step) in ADAM7 {
+                let pass_width = header.width.saturating_sub(x_start).div_ceil(x_step);
+                let pass_height = header.height.saturating_sub(y_start).div_ceil(y_step);
+                if pass_width == 0 || pass_height == 0 {
+                    continue;
+                }
+
+                let length = (header.row_bytes(pass_width) + 1) * pass_height;
+                let pass = filtered.get(offset..offset + length)?;
+                offset += length;
+
+                png.decode_pass(&decoder, pass, pass_width, pass_height, |x, y| {
+                    (x_start + x * x_step, y_start + y * y_step)
+                })?;
+            }
+        } else {
+            png.decode_pass(&decoder, &filtered, header.width, header.height, |x, y| {
+                (x, y)
+            })?;
+        }
+
+        Some(png)
+    }
+
+    // unfilter one (sub-)image and store its pixels at `position(x, y)`
+    fn decode_pass(
+        &mut self,
+        decoder: &PixelDecoder,
+        data: &[u8],
+        width: usize,
+        height: usize,
+        position: impl Fn(usize, usize) -> (usize, usize),
+    ) -> Option<()> {
+        let row_bytes = decoder.header.row_bytes(width);
+        let stride = decoder.header.filter_stride();
+        let mut previous = vec![0u8; row_bytes];
+        let mut current = vec![0u8; row_bytes];
+
+        for y in 0..height {
+            let line = data.get(y * (row_bytes + 1)..(y + 1) * (row_bytes + 1))?;
+            current.copy_from_slice(&line[1..]);
+            unfilter(line[0], &mut current, &previous, stride)?;
+
+            for x in 0..width {
+                let (dest_x, dest_y) = position(x, y);
+                self.pixels[dest_y * self.width + dest_x] = decoder.pixel(&current, x);
+            }
+
+            core::mem::swap(&mut previous, &mut current);
+        }
+
+        Some(())
+    }
+}
+
+impl Image for Png {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> screen::Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+struct PixelDecoder<'a> {
+    header: &'a PngHeader,
+    palette: &'a [screen::Color],
+    transparency: &'a Transparency,
+}
+
+impl PixelDecoder<'_> {
+    // sample `channel` of pixel `x`, still in the image's bit depth
+    fn sample(&self, row: &[u8], x: usize, channel: usize) -> u16 {
+        let depth = self.header.bit_depth as usize;
+        let index = x * self.header.channels() + channel;
+        match depth {
+            16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+            8 => row[index] as u16,
+            _ => {
+                // packed samples, leftmost in the most significant bits
+                let bit = index * depth;
+                let shift = 8 - depth - (bit % 8);
+                ((row[bit / 8] >> shift) & ((1 << depth) - 1)) as u16
+            }
+        }
+    }
+
+    fn to_8bit(&self, sample: u16) -> u8 {
+        match self.header.bit_depth {
+            16 => (sample >> 8) as u8,
+            8 => sample as u8,
+            depth => (sample as u32 * 0xff / ((1 << depth) - 1)) as u8,
+        }
+    }
+
+    fn pixel(&self, row: &[u8], x: usize) -> screen::Color {
+        let sample = |channel| self.sample(row, x, channel);
+
+        match self.header.color_type {
+            COLOR_PALETTE => self
+                .palette
+                .get(sample(0) as usize)
+                .copied()
+                .unwrap_or_default(),
+            COLOR_GRAY | COLOR_GRAY_ALPHA => {
+                let gray = sample(0);
+                let alpha = match (self.header.color_type, self.transparency) {
+                    (COLOR_GRAY_ALPHA, _) => self.to_8bit(sample(1)),
+                    (_, Transparency::Gray(key)) if *key == gray => 0,
+                    _ => 0xff,
+                };
+                let gray = self.to_8bit(gray);
+                screen::Color {
+                    red: gray,
+                    green: gray,
+                    blue: gray,
+                    alpha,
+                }
+            }
+            _ => {
+                let (red, green, blue) = (sample(0), sample(1), sample(2));
+                let alpha = match (self.header.color_type, self.transparency) {
+                    (COLOR_RGBA, _) => self.to_8bit(sample(3)),
+                    (_, Transparency::Rgb(r, g, b)) if (*r, *g, *b) == (red, green, blue) => 0,
+                    _ => 0xff,
+                };
+                screen::Color {
+                    red: self.to_8bit(red),
+                    green: self.to_8bit(green),
+                    blue: self.to_8bit(blue),
+                    alpha,
+                }
+            }
+        }
+    }
+}
+
+fn unfilter(filter: u8, current: &mut [u8], previous: &[u8], stride: usize) -> Option<()> {
+    for i in 0..current.len() {
+        let left = if i >= stride { current[i - stride] } else { 0 };
+        let up = previous[i];
+        let up_left = if i >= stride { previous[i - stride] } else { 0 };
+
+        let predictor = match filter {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((left as u16 + up as u16) / 2) as u8,
+            4 => paeth(left, up, up_left),
+            _ => return None,
+        };
+        current[i] = current[i].wrapping_add(predictor);
+    }
+
+    Some(())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn read_u16(raw: &[u8], offset: usize) -> Option<u16> {
+    raw.get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(raw: &[u8], offset: usize) -> Option<u32> {
+    raw.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn parse_header(data: &[u8]) -> Option<PngHeader> {
+    let header = PngHeader {
+        width: read_u32(data, 0)? as usize,
+        height: read_u32(data, 4)? as usize,
+        bit_depth: *data.get(8)?,
+        color_type: *data.get(9)?,
+        interlaced: *data.get(12)? == 1,
+    };
+
+    // compression and filter method must both be 0
+    if header.width == 0 || header.height == 0 || data[10] != 0 || data[11] != 0 {
+        return None;
+    }
+
+    header.is_valid().then_some(header)
+}
+
+// (type, data) pairs; stops at the first truncated chunk
+struct ChunkIter<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> ChunkIter<'a> {
+    fn new(raw: &'a [u8]) -> Self {
+        ChunkIter { raw }
+    }
+}
+
+impl<'a> Iterator for ChunkIter<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let length = read_u32(self.raw, 0)? as usize;
+        let chunk_type = self.raw.get(4..8)?;
+        let data = self.raw.get(8..8usize.checked_add(length)?)?;
+
+        // length + type + data + crc
+        self.raw = self.raw.get(12 + length..)?;
+        Some((chunk_type, data))
+    }
+}
+
+pub fn get_png_dimensions(raw_png: &[u8]) -> Option<(usize, usize)> {
+    if !raw_png.starts_with(PNG_SIGNATURE) {
+        return None;
+    }
+
+    let (chunk_type, data) = ChunkIter::new(&raw_png[PNG_SIGNATURE.len()..]).next()?;
+    if chunk_type != b"IHDR" {
+        return None;
+    }
+    parse_header(data).map(|header| (header.width, header.height))
+}
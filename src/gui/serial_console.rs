@@ -0,0 +1,109 @@
+use crate::gui::{Console, Cursor};
+use crate::util::*;
+
+extern crate alloc;
+use alloc::format;
+
+use uefi::boot::{self, OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol};
+use uefi::proto::console::serial::Serial;
+
+// assumed VT100 terminal on the other end
+const TERMINAL_COLUMNS: usize = 80;
+const TERMINAL_ROWS: usize = 25;
+
+// raw serial port, ANSI sequences are passed through to the terminal
+pub struct SerialConsole {
+    serial: ScopedProtocol<Serial>,
+    cursor: Cursor,
+}
+
+impl SerialConsole {
+    // first serial port; opened shared so the firmware terminal driver keeps working
+    pub fn new() -> Option<Self> {
+        let handle = boot::get_handle_for_protocol::<Serial>().ok()?;
+        let serial = unsafe {
+            boot::open_protocol::<Serial>(
+                OpenProtocolParams {
+                    handle,
+                    agent: boot::image_handle(),
+                    controller: None,
+                },
+                OpenProtocolAttributes::GetProtocol,
+            )
+        }
+        .ok()?;
+
+        Some(SerialConsole {
+            serial,
+            cursor: Cursor::default(),
+        })
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> BootResult<()> {
+        self.serial
+            .write(bytes)
+            .map_err(|_| uefi_error(uefi::Status::DEVICE_ERROR))
+    }
+}
+
+impl Console for SerialConsole {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn write_str(&mut self, s: &str) -> BootResult<()> {
+        let mut in_escape = false;
+
+        // terminals expect CRLF
+        for line in s.split_inclusive('\n') {
+            match line.strip_suffix('\n') {
+                Some(content) => {
+                    self.write_bytes(content.as_bytes())?;
+                    self.write_bytes(b"\r\n")?;
+                }
+                None => self.write_bytes(line.as_bytes())?,
+            }
+        }
+
+        // keep a best-effort cursor so menus can ask for it
+        for character in s.chars() {
+            match character {
+                '\x1b' => in_escape = true,
+                _ if in_escape => in_escape = !character.is_ascii_alphabetic(),
+                '\n' => {
+                    self.cursor.x = 0;
+                    self.cursor.y = (self.cursor.y + 1).min(TERMINAL_ROWS - 1);
+                }
+                '\r' => self.cursor.x = 0,
+                '\x08' => self.cursor.x = self.cursor.x.saturating_sub(1),
+                _ => {
+                    self.cursor.x += 1;
+                    if self.cursor.x >= TERMINAL_COLUMNS {
+                        self.cursor.x = 0;
+                        self.cursor.y = (self.cursor.y + 1).min(TERMINAL_ROWS - 1);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn clear(&mut self) -> BootResult<()> {
+        self.cursor = Cursor::default();
+        self.write_bytes(b"\x1b[2J\x1b[H")
+    }
+
+    fn cursor(&self) -> BootResult<Cursor> {
+        Ok(self.cursor)
+    }
+
+    fn set_cursor(&mut self, cursor: &Cursor) -> BootResult<()> {
+        self.cursor = *cursor;
+        self.write_bytes(format!("\x1b[{};{}H", cursor.y + 1, cursor.x + 1).as_bytes())
+    }
+
+    fn size(&self) -> BootResult<(usize, usize)> {
+        Ok((TERMINAL_COLUMNS, TERMINAL_ROWS))
+    }
+}
@@ -0,0 +1,169 @@
+use crate::gui::{Console, Cursor};
+use crate::util::*;
+
+use core::fmt::Write;
+
+use uefi::proto::console::text::Color;
+
+// ESC [ 30-37 m -> EFI text attributes
+const ANSI_TO_TEXT_COLOR: [Color; 8] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Brown,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightGray,
+];
+
+const BRIGHT_ANSI_TO_TEXT_COLOR: [Color; 8] = [
+    Color::DarkGray,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::Yellow,
+    Color::LightBlue,
+    Color::LightMagenta,
+    Color::LightCyan,
+    Color::White,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EscapeState {
+    Normal,
+    Escape,
+    Csi,
+}
+
+// UEFI ConOut; it doesn't understand ANSI sequences, so SGR colors become text attributes
+pub struct TextConsole {
+    escape: EscapeState,
+    parameters: [u8; 16],
+    parameters_length: usize,
+    foreground: Color,
+    background: Color,
+}
+
+impl TextConsole {
+    pub fn new() -> Self {
+        TextConsole {
+            escape: EscapeState::Normal,
+            parameters: [0; 16],
+            parameters_length: 0,
+            foreground: Color::LightGray,
+            background: Color::Black,
+        }
+    }
+
+    fn apply_parameters(&mut self) {
+        let parameters =
+            core::str::from_utf8(&self.parameters[..self.parameters_length]).unwrap_or("");
+
+        for code in parameters.split(';') {
+            match code.parse::<usize>().unwrap_or(0) {
+                0 => {
+                    self.foreground = Color::LightGray;
+                    self.background = Color::Black;
+                }
+                code @ 30..=37 => self.foreground = ANSI_TO_TEXT_COLOR[code - 30],
+                39 => self.foreground = Color::LightGray,
+                // EFI only has 8 background colors
+                code @ 40..=47 => self.background = ANSI_TO_TEXT_COLOR[code - 40],
+                49 => self.background = Color::Black,
+                code @ 90..=97 => self.foreground = BRIGHT_ANSI_TO_TEXT_COLOR[code - 90],
+                _ => {}
+            }
+        }
+
+        let (foreground, background) = (self.foreground, self.background);
+        uefi::system::with_stdout(|stdout| {
+            let _ = stdout.set_color(foreground, background);
+        });
+    }
+}
+
+impl Default for TextConsole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Console for TextConsole {
+    fn name(&self) -> &'static str {
+        "conout"
+    }
+
+    fn write_str(&mut self, s: &str) -> BootResult<()> {
+        let mut plain_start = 0;
+
+        for (index, character) in s.char_indices() {
+            match (self.escape, character) {
+                (EscapeState::Normal, '\x1b') => {
+                    write_stdout(&s[plain_start..index])?;
+                    self.escape = EscapeState::Escape;
+                }
+                (EscapeState::Normal, _) => continue,
+                (EscapeState::Escape, '[') => {
+                    self.escape = EscapeState::Csi;
+                    self.parameters_length = 0;
+                }
+                (EscapeState::Escape, _) => self.escape = EscapeState::Normal,
+                (EscapeState::Csi, '0'..='9' | ';') => {
+                    if self.parameters_length < self.parameters.len() {
+                        self.parameters[self.parameters_length] = character as u8;
+                        self.parameters_length += 1;
+                    }
+                }
+                (EscapeState::Csi, _) => {
+                    if character == 'm' {
+                        self.apply_parameters();
+                    }
+                    self.escape = EscapeState::Normal;
+                }
+            }
+            plain_start = index + character.len_utf8();
+        }
+
+        if self.escape == EscapeState::Normal {
+            write_stdout(&s[plain_start..])?;
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) -> BootResult<()> {
+        uefi::system::with_stdout(|stdout| stdout.clear()).map_err(BootError::from)
+    }
+
+    fn cursor(&self) -> BootResult<Cursor> {
+        let (x, y) = uefi::system::with_stdout(|stdout| stdout.cursor_position());
+        Ok(Cursor { x, y })
+    }
+
+    fn set_cursor(&mut self, cursor: &Cursor) -> BootResult<()> {
+        uefi::system::with_stdout(|stdout| stdout.set_cursor_position(cursor.x, cursor.y))
+            .map_err(BootError::from)
+    }
+
+    fn size(&self) -> BootResult<(usize, usize)> {
+        uefi::system::with_stdout(|stdout| {
+            stdout
+                .current_mode()
+                .ok()
+                .flatten()
+                .map(|mode| (mode.columns(), mode.rows()))
+                .ok_or(uefi_error(uefi::Status::UNSUPPORTED))
+        })
+    }
+}
+
+fn write_stdout(s: &str) -> BootResult<()> {
+    if s.is_empty() {
+        return Ok(());
+    }
+
+    uefi::system::with_stdout(|stdout| {
+        stdout
+            .write_str(s)
+            .map_err(|_| uefi_error(uefi::Status::DEVICE_ERROR))
+    })
+}
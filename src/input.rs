@@ -0,0 +1,94 @@
+// keyboard input through uefi simple text input (ConIn)
+
+use uefi::proto::console::text::{Key as UefiKey, ScanCode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Escape,
+    Backspace,
+    Tab,
+    Function(u8),
+    Char(char),
+    Other,
+}
+
+impl From<UefiKey> for Key {
+    fn from(key: UefiKey) -> Self {
+        match key {
+            UefiKey::Printable(c) => match char::from(c) {
+                '\r' | '\n' => Key::Enter,
+                '\x08' => Key::Backspace,
+                '\t' => Key::Tab,
+                c => Key::Char(c),
+            },
+            UefiKey::Special(ScanCode::UP) => Key::Up,
+            UefiKey::Special(ScanCode::DOWN) => Key::Down,
+            UefiKey::Special(ScanCode::LEFT) => Key::Left,
+            UefiKey::Special(ScanCode::RIGHT) => Key::Right,
+            UefiKey::Special(ScanCode::PAGE_UP) => Key::PageUp,
+            UefiKey::Special(ScanCode::PAGE_DOWN) => Key::PageDown,
+            UefiKey::Special(ScanCode::HOME) => Key::Home,
+            UefiKey::Special(ScanCode::END) => Key::End,
+            UefiKey::Special(ScanCode::ESCAPE) => Key::Escape,
+            UefiKey::Special(scan_code) => match scan_code.0 {
+                // F1 (0x0b) ..= F10 (0x14), F11 (0x15), F12 (0x16)
+                code @ 0x0b..=0x16 => Key::Function((code - 0x0b + 1) as u8),
+                _ => Key::Other,
+            },
+        }
+    }
+}
+
+// return the pending key (if any) without blocking
+pub fn poll_key() -> Option<Key> {
+    uefi::system::with_stdin(|stdin| stdin.read_key().ok().flatten().map(Key::from))
+}
+
+// block until a key is pressed
+pub fn wait_key() -> Key {
+    loop {
+        if let Some(key) = poll_key() {
+            return key;
+        }
+
+        let event = uefi::system::with_stdin(|stdin| stdin.wait_for_key_event());
+        match event {
+            Some(event) => {
+                let _ = uefi::boot::wait_for_event(&mut [event]);
+            }
+            None => uefi::boot::stall(10_000),
+        }
+    }
+}
+
+// wait for a key up to `timeout_ms` milliseconds
+pub fn wait_key_timeout(timeout_ms: usize) -> Option<Key> {
+    const POLL_INTERVAL_MS: usize = 10;
+
+    let mut elapsed = 0;
+    loop {
+        if let Some(key) = poll_key() {
+            return Some(key);
+        }
+        if elapsed >= timeout_ms {
+            return None;
+        }
+
+        uefi::boot::stall(POLL_INTERVAL_MS * 1000);
+        elapsed += POLL_INTERVAL_MS;
+    }
+}
+
+// drop any keys typed before we were ready to read them
+pub fn flush_keys() {
+    while poll_key().is_some() {}
+}
@@ -16,31 +16,106 @@ pub use boot_info::*;
 mod frame_buffer_info;
 pub use frame_buffer_info::*;
 
+mod slot;
+pub use slot::*;
+
+mod chainload;
+pub use chainload::*;
+
+mod ext2;
+pub use ext2::*;
+
+mod gpt;
+pub use gpt::*;
+
+mod tftp;
+pub use tftp::*;
+
+mod volume;
+pub use volume::*;
+
+mod compression;
+pub use compression::*;
+
+mod archive;
+pub use archive::*;
+
+mod boot_module;
+pub use boot_module::*;
+
+mod symbols;
+pub use symbols::*;
+
 use crate::info;
 use crate::util::*;
 
-const KERNEL_PATH: &str = r"\kernel\kernel.elf";
-const INIT_PATH: &str = r"\kernel\init.elf";
+extern crate alloc;
+use alloc::format;
+use alloc::vec;
+
+const KERNEL_FILE: &str = "kernel.elf";
+const INIT_FILE: &str = "init.elf";
+
+// file contents, decompressed if they are
+fn read_image(volume: &Volume, path: &str) -> BootResult<vec::Vec<u8>> {
+    volume
+        .read_entire_file(path)
+        .and_then(|bytes| decompress_image(path, bytes))
+}
 
 pub fn run() -> BootResult<()> {
     info!("Starting load a kernel...");
+    // drop whatever a previous failed attempt left allocated, and anything pointing into it
+    release_allocations();
+    unsafe {
+        BOOT_INFO.kernel_image_info = KernelImageInfo::EMPTY;
+        BOOT_INFO.kernel_symbol_info = SymbolTableInfo::EMPTY;
+        BOOT_INFO.init_symbol_info = SymbolTableInfo::EMPTY;
+    }
     let mut kernel_entry_point: usize = 0;
+    let kernel_path = slot_path(KERNEL_FILE);
+    let init_path = slot_path(INIT_FILE);
+    let volume = find_kernel_volume(&crate::config::current().volume, &kernel_path)?;
 
-    read_entire_file(KERNEL_PATH).and_then(|kernel_bytes| {
+    read_image(&volume, &kernel_path).and_then(|kernel_bytes| {
         parse_elf(&kernel_bytes)
-            .and_then(|kernel_elf| load_kernel_at_physical_address(&kernel_elf, &kernel_bytes))
-            .map(|entry_point| {
+            .and_then(|kernel_elf| {
+                let kernel_image_info =
+                    load_kernel_at_physical_address(&kernel_elf, &kernel_bytes)?;
+                if crate::config::current().symbols {
+                    let symbol_info = load_symbol_table(&kernel_elf, "the kernel")?;
+                    unsafe { BOOT_INFO.kernel_symbol_info = symbol_info };
+                }
+                Ok(kernel_image_info)
+            })
+            .with_context(|| format!("failed to load the kernel {}", kernel_path))
+            .map(|kernel_image_info| {
                 info!(
                     "Kernel loaded successfully at entry point: 0x{:016x}",
-                    entry_point
+                    kernel_image_info.entry_point_physical_address
                 );
-                kernel_entry_point = entry_point;
+                info!(
+                    "Kernel image: [0x{:016x}, 0x{:016x}), segments: {}",
+                    kernel_image_info.physical_start,
+                    kernel_image_info.physical_end,
+                    kernel_image_info.segment_count
+                );
+                kernel_entry_point = kernel_image_info.entry_point_physical_address;
+                unsafe { BOOT_INFO.kernel_image_info = kernel_image_info };
             })
             .and_then(|_| reserve_ap_trampoline())
-            .and_then(|_| read_entire_file(INIT_PATH))
+            .and_then(|_| read_image(&volume, &init_path))
             .and_then(|init_bytes| {
                 parse_elf(&init_bytes)
-                    .and_then(|init_elf| load_init_at_anywhere(&init_elf, &init_bytes))
+                    .and_then(|init_elf| {
+                        let init_image_info = load_init_at_anywhere(&init_elf, &init_bytes)?;
+                        if crate::config::current().symbols_init {
+                            let symbol_info = load_symbol_table(&init_elf, "init")?;
+                            unsafe { BOOT_INFO.init_symbol_info = symbol_info };
+                        }
+                        Ok(init_image_info)
+                    })
+                    .with_context(|| format!("failed to load init {}", init_path))
                     .map(|fetched_init_image_info| {
                         info!(
                             "Init loaded successfully at entry point: 0x{:016x}",
@@ -59,6 +134,7 @@ pub fn run() -> BootResult<()> {
                         info!("Init image info prepared.");
                     })
             })
+            .and_then(|_| load_boot_modules(&volume))
             .and_then(|_| {
                 info!("Preparing memory info...");
                 make_memory_info().map(|memory_info| {
@@ -70,14 +146,18 @@ pub fn run() -> BootResult<()> {
                         );
                     }
                     unsafe { BOOT_INFO.memory_info = memory_info };
-                    
                 })
             })
             .map(|_| {
-                // arch_info[0]: rsdp
                 unsafe {
-                    BOOT_INFO.arch_info[0] = find_rsdp_address();
+                    BOOT_INFO.arch_info[ARCH_INFO_RSDP] = find_rsdp_address();
                     info!("Loading finished. Preparing to jump to kernel...");
+                    if crate::config::current().pause {
+                        crate::print::pause(
+                            "Press any key to jump to the kernel (PageUp/PageDown to scroll)",
+                        );
+                    }
+                    crate::log::flush();
                     let _ = uefi::boot::exit_boot_services(Some(
                         uefi::mem::memory_map::MemoryType::LOADER_DATA,
                     ));
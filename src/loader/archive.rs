@@ -0,0 +1,188 @@
+use crate::util::*;
+
+extern crate alloc;
+use alloc::string::{String, ToString};
+use alloc::vec;
+
+const TAR_BLOCK_SIZE: usize = 512;
+const TAR_MAGIC: &[u8] = b"ustar";
+// regular files are '0', or NUL in pre-POSIX archives
+const TAR_TYPE_REGULAR: [u8; 2] = [b'0', 0];
+// GNU long name: the data is the name of the next member
+const TAR_TYPE_GNU_LONG_NAME: u8 = b'L';
+
+const CPIO_NEWC_MAGIC: &[u8] = b"070701";
+const CPIO_NEWC_CRC_MAGIC: &[u8] = b"070702";
+const CPIO_HEADER_SIZE: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+const CPIO_MODE_TYPE_MASK: u32 = 0o170000;
+const CPIO_MODE_REGULAR: u32 = 0o100000;
+
+// a regular file in the archive: `size` bytes at `offset` from the start of the archive
+#[derive(Debug, Clone)]
+pub struct ArchiveMember {
+    pub name: String,
+    pub offset: usize,
+    pub size: usize,
+}
+
+fn archive_error(reason: &'static str) -> BootError {
+    BootError::FileSystem { reason }
+}
+
+fn is_tar(bytes: &[u8]) -> bool {
+    bytes.len() >= TAR_BLOCK_SIZE && bytes[257..262] == *TAR_MAGIC
+}
+
+fn is_cpio(bytes: &[u8]) -> bool {
+    bytes.starts_with(CPIO_NEWC_MAGIC) || bytes.starts_with(CPIO_NEWC_CRC_MAGIC)
+}
+
+// ustar or newc cpio, told apart by their magic; directories, links and devices are skipped
+pub fn parse_archive(bytes: &[u8]) -> BootResult<vec::Vec<ArchiveMember>> {
+    if is_tar(bytes) {
+        parse_tar(bytes)
+    } else if is_cpio(bytes) {
+        parse_cpio(bytes)
+    } else {
+        Err(archive_error("not a ustar or newc cpio archive"))
+    }
+}
+
+// `./bin/server` and `/bin/server` both become `bin/server`
+fn member_name(raw: &[u8]) -> String {
+    let name = String::from_utf8_lossy(raw);
+    name.trim_start_matches("./")
+        .trim_start_matches('/')
+        .to_string()
+}
+
+// NUL terminated, or the whole field
+fn field(bytes: &[u8]) -> &[u8] {
+    let end = bytes
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(bytes.len());
+    &bytes[..end]
+}
+
+fn parse_octal(bytes: &[u8]) -> Option<usize> {
+    let digits = core::str::from_utf8(field(bytes)).ok()?.trim();
+    if digits.is_empty() {
+        return Some(0);
+    }
+    usize::from_str_radix(digits, 8).ok()
+}
+
+fn tar_checksum_ok(header: &[u8]) -> bool {
+    // computed with the checksum field itself read as spaces
+    let sum = header
+        .iter()
+        .enumerate()
+        .map(|(index, byte)| match index {
+            148..156 => b' ' as usize,
+            _ => *byte as usize,
+        })
+        .sum::<usize>();
+    parse_octal(&header[148..156]) == Some(sum)
+}
+
+fn parse_tar(bytes: &[u8]) -> BootResult<vec::Vec<ArchiveMember>> {
+    let mut members = vec::Vec::new();
+    let mut long_name = None;
+    let mut offset = 0;
+
+    while offset + TAR_BLOCK_SIZE <= bytes.len() {
+        let header = &bytes[offset..offset + TAR_BLOCK_SIZE];
+        // end of archive
+        if header.iter().all(|byte| *byte == 0) {
+            break;
+        }
+        if !tar_checksum_ok(header) {
+            return Err(archive_error("tar header checksum mismatch"));
+        }
+
+        let size = parse_octal(&header[124..136]).ok_or(archive_error("invalid tar size"))?;
+        let data = offset + TAR_BLOCK_SIZE;
+        if data + size > bytes.len() {
+            return Err(archive_error("truncated tar member"));
+        }
+
+        let type_flag = header[156];
+        if type_flag == TAR_TYPE_GNU_LONG_NAME {
+            long_name = Some(member_name(field(&bytes[data..data + size])));
+        } else {
+            let name = long_name.take().unwrap_or_else(|| {
+                // ustar splits long paths into prefix and name
+                let prefix = field(&header[345..500]);
+                let name = field(&header[0..100]);
+                if prefix.is_empty() {
+                    member_name(name)
+                } else {
+                    member_name(&[prefix, b"/", name].concat())
+                }
+            });
+            if TAR_TYPE_REGULAR.contains(&type_flag) && !name.is_empty() {
+                members.push(ArchiveMember {
+                    name,
+                    offset: data,
+                    size,
+                });
+            }
+        }
+
+        offset = data + align_up(size, TAR_BLOCK_SIZE);
+    }
+
+    Ok(members)
+}
+
+fn parse_hex(bytes: &[u8]) -> Option<usize> {
+    usize::from_str_radix(core::str::from_utf8(bytes).ok()?, 16).ok()
+}
+
+fn parse_cpio(bytes: &[u8]) -> BootResult<vec::Vec<ArchiveMember>> {
+    let mut members = vec::Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let header = bytes
+            .get(offset..offset + CPIO_HEADER_SIZE)
+            .ok_or(archive_error("cpio archive without a trailer"))?;
+        if !is_cpio(header) {
+            return Err(archive_error("bad cpio header magic"));
+        }
+
+        // 13 eight digit hex fields after the magic
+        let hex_field = |index: usize| parse_hex(&header[6 + index * 8..14 + index * 8]);
+        let invalid = || archive_error("invalid cpio header");
+        let mode = hex_field(1).ok_or_else(invalid)? as u32;
+        let size = hex_field(6).ok_or_else(invalid)?;
+        let name_size = hex_field(11).ok_or_else(invalid)?;
+
+        let name_start = offset + CPIO_HEADER_SIZE;
+        // header and name, then the data, are each padded to 4 bytes
+        let data = align_up(name_start + name_size, 4);
+        if name_size == 0 || data + size > bytes.len() {
+            return Err(archive_error("truncated cpio member"));
+        }
+
+        let name = field(&bytes[name_start..name_start + name_size]);
+        if name == CPIO_TRAILER.as_bytes() {
+            break;
+        }
+
+        let name = member_name(name);
+        if mode & CPIO_MODE_TYPE_MASK == CPIO_MODE_REGULAR && !name.is_empty() {
+            members.push(ArchiveMember {
+                name,
+                offset: data,
+                size,
+            });
+        }
+
+        offset = align_up(data + size, 4);
+    }
+
+    Ok(members)
+}
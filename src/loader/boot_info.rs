@@ -1,26 +1,111 @@
+use crate::loader::BootModule;
+use crate::loader::FramebufferInfo;
 use crate::loader::InitImageInfo;
+use crate::loader::KernelImageInfo;
 use crate::loader::MemoryInfo;
+use crate::loader::SymbolTableInfo;
 
 pub const ARCH_INFO_MAX: usize = 128;
 
+// +----------+------------------------------------------+
+// | idx      | description                              |
+// +----------+------------------------------------------+
+// | [ 0]     | rsdp                                     |
+// | [ 1..14] | framebuffer (FramebufferInfo::serialize) |
+// | [14]     | framebuffer present (0: headless)        |
+// +----------+------------------------------------------+
+pub const ARCH_INFO_RSDP: usize = 0;
+pub const ARCH_INFO_FRAMEBUFFER: usize = 1;
+pub const ARCH_INFO_FRAMEBUFFER_PRESENT: usize = ARCH_INFO_FRAMEBUFFER + 13;
+
+// loader log ring in RESERVED memory: `length` bytes of plain text starting at
+// `address + start`, wrapping around at `address + size`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LogBufferInfo {
+    pub address: usize,
+    pub size: usize,
+    pub start: usize,
+    pub length: usize,
+}
+
+// A/B slot this boot came from; flags are BOOT_SLOT_TRIAL and BOOT_SLOT_ROLLED_BACK.
+// a trial boot has to write the A9NBootSuccess variable or it gets rolled back
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BootSlotInfo {
+    pub slot: usize,
+    pub flags: usize,
+}
+
+// unique GUID of the GPT partition the kernel was read from (on-disk byte order),
+// all zero if it didn't come from a GPT partition
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BootPartitionInfo {
+    pub partition_guid: [u8; 16],
+}
+
+// members of initrd.tar / initrd.cpio: `module_count` BootModule entries at `modules`,
+// all pointing into the archive loaded at `archive_address` (RESERVED memory)
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BootModuleInfo {
+    pub modules: *const BootModule,
+    pub module_count: usize,
+    pub archive_address: usize,
+    pub archive_size: usize,
+}
+
+impl BootModuleInfo {
+    pub const EMPTY: Self = BootModuleInfo {
+        modules: core::ptr::null(),
+        module_count: 0,
+        archive_address: 0,
+        archive_size: 0,
+    };
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct BootInfo {
     pub memory_info: MemoryInfo,
     pub init_image_info: InitImageInfo,
     pub arch_info: [usize; 128],
+    pub log_buffer_info: LogBufferInfo,
+    pub boot_slot_info: BootSlotInfo,
+    pub boot_partition_info: BootPartitionInfo,
+    pub boot_module_info: BootModuleInfo,
+    pub kernel_image_info: KernelImageInfo,
+    pub kernel_symbol_info: SymbolTableInfo,
+    pub init_symbol_info: SymbolTableInfo,
 }
 
 impl BootInfo {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         memory_info: MemoryInfo,
         init_image_info: InitImageInfo,
         arch_info: [usize; ARCH_INFO_MAX],
+        log_buffer_info: LogBufferInfo,
+        boot_slot_info: BootSlotInfo,
+        boot_partition_info: BootPartitionInfo,
+        boot_module_info: BootModuleInfo,
+        kernel_image_info: KernelImageInfo,
+        kernel_symbol_info: SymbolTableInfo,
+        init_symbol_info: SymbolTableInfo,
     ) -> Self {
         BootInfo {
             memory_info,
             init_image_info,
             arch_info,
+            log_buffer_info,
+            boot_slot_info,
+            boot_partition_info,
+            boot_module_info,
+            kernel_image_info,
+            kernel_symbol_info,
+            init_symbol_info,
         }
     }
 }
@@ -39,4 +124,31 @@ pub static mut BOOT_INFO: BootInfo = BootInfo {
         init_ipc_buffer_virtual_address: 0,
     },
     arch_info: [0; ARCH_INFO_MAX],
+    log_buffer_info: LogBufferInfo {
+        address: 0,
+        size: 0,
+        start: 0,
+        length: 0,
+    },
+    boot_slot_info: BootSlotInfo { slot: 0, flags: 0 },
+    boot_partition_info: BootPartitionInfo {
+        partition_guid: [0; 16],
+    },
+    boot_module_info: BootModuleInfo::EMPTY,
+    kernel_image_info: KernelImageInfo::EMPTY,
+    kernel_symbol_info: SymbolTableInfo::EMPTY,
+    init_symbol_info: SymbolTableInfo::EMPTY,
 };
+
+// without a framebuffer every framebuffer slot is zeroed and the present flag is cleared
+pub fn set_framebuffer_info(frame_buffer_info: Option<&FramebufferInfo>) {
+    let serialized_info = frame_buffer_info
+        .map(FramebufferInfo::serialize)
+        .unwrap_or([0; 13]);
+
+    unsafe {
+        BOOT_INFO.arch_info[ARCH_INFO_FRAMEBUFFER..ARCH_INFO_FRAMEBUFFER_PRESENT]
+            .copy_from_slice(&serialized_info);
+        BOOT_INFO.arch_info[ARCH_INFO_FRAMEBUFFER_PRESENT] = frame_buffer_info.is_some() as usize;
+    }
+}
@@ -0,0 +1,135 @@
+use crate::loader::{
+    BOOT_INFO, BootModuleInfo, Volume, parse_archive, read_image, slot_path, track_allocation,
+};
+use crate::util::*;
+use crate::{debug, info, warn};
+
+use core::ptr::copy_nonoverlapping;
+
+extern crate alloc;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+
+use uefi::boot::{self, MemoryType};
+
+pub const BOOT_MODULE_MAX: usize = 64;
+pub const BOOT_MODULE_NAME_MAX: usize = 128;
+
+// tried in order next to kernel.elf; the format comes from the contents, not the name
+const ARCHIVE_FILES: [&str; 2] = ["initrd.tar", "initrd.cpio"];
+
+// one archive member; `address` points into the loaded archive, `name` is NUL padded
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BootModule {
+    pub name: [u8; BOOT_MODULE_NAME_MAX],
+    pub address: usize,
+    pub size: usize,
+}
+
+static mut BOOT_MODULES: [BootModule; BOOT_MODULE_MAX] = [BootModule {
+    name: [0; BOOT_MODULE_NAME_MAX],
+    address: 0,
+    size: 0,
+}; BOOT_MODULE_MAX];
+
+// the first archive that exists, None if there is none
+fn read_archive(volume: &Volume) -> BootResult<Option<(String, vec::Vec<u8>)>> {
+    for file in ARCHIVE_FILES {
+        let path = slot_path(file);
+        match read_image(volume, &path) {
+            Ok(bytes) => return Ok(Some((path, bytes))),
+            Err(e) if matches!(e.root(), BootError::FileNotFound { .. }) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+// RESERVED pages, so members starting at a page multiple in the archive stay page aligned
+fn place_archive(bytes: &[u8]) -> BootResult<usize> {
+    let pages = bytes_to_pages_rounded(bytes.len());
+    let address = boot::allocate_pages(boot::AllocateType::AnyPages, MemoryType::RESERVED, pages)
+        .map_err(|e| BootError::AllocationFailed {
+            address: None,
+            pages,
+            memory_type: MemoryType::RESERVED,
+            status: e.status(),
+        })?
+        .as_ptr()
+        .addr();
+    track_allocation(address, pages);
+
+    unsafe { copy_nonoverlapping(bytes.as_ptr(), address as *mut u8, bytes.len()) };
+    Ok(address)
+}
+
+// initrd.tar / initrd.cpio members as named modules for init; no archive means no modules
+pub fn load_boot_modules(volume: &Volume) -> BootResult<()> {
+    unsafe {
+        BOOT_INFO.boot_module_info = BootModuleInfo::EMPTY;
+    }
+
+    let Some((path, bytes)) = read_archive(volume)? else {
+        info!("No boot module archive");
+        return Ok(());
+    };
+
+    let members = parse_archive(&bytes).with_context(|| format!("failed to parse {}", path))?;
+    let address = place_archive(&bytes).with_context(|| format!("failed to load {}", path))?;
+
+    let mut count = 0;
+    for member in &members {
+        if count == BOOT_MODULE_MAX {
+            warn!(
+                "{}: only the first {} of {} members are passed on",
+                path,
+                BOOT_MODULE_MAX,
+                members.len()
+            );
+            break;
+        }
+        // leave room for the terminating NUL
+        if member.name.len() >= BOOT_MODULE_NAME_MAX {
+            warn!("{}: skipping {}, the name is too long", path, member.name);
+            continue;
+        }
+
+        let mut name = [0u8; BOOT_MODULE_NAME_MAX];
+        name[..member.name.len()].copy_from_slice(member.name.as_bytes());
+        let module = BootModule {
+            name,
+            address: address + member.offset,
+            size: member.size,
+        };
+        debug!(
+            "Boot module {}: at 0x{:016x}, {} bytes{}",
+            member.name,
+            module.address,
+            module.size,
+            if module.address.is_multiple_of(EFI_PAGE_SIZE) {
+                ", page aligned"
+            } else {
+                ""
+            }
+        );
+
+        unsafe { BOOT_MODULES[count] = module };
+        count += 1;
+    }
+
+    info!(
+        "Loaded {} boot modules from {} at 0x{:016x}",
+        count, path, address
+    );
+    unsafe {
+        BOOT_INFO.boot_module_info = BootModuleInfo {
+            modules: core::ptr::addr_of!(BOOT_MODULES).cast(),
+            module_count: count,
+            archive_address: address,
+            archive_size: bytes.len(),
+        };
+    }
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use crate::info;
+use crate::util::*;
+
+extern crate alloc;
+use alloc::vec;
+
+use uefi::boot::{self, LoadImageSource};
+use uefi::proto::BootPolicy;
+use uefi::proto::device_path::DevicePath;
+use uefi::proto::device_path::build::{self, DevicePathBuilder};
+use uefi::proto::loaded_image::LoadedImage;
+use uefi::{CString16, Handle};
+
+// load `path` from the loader's volume and run it; returns once the child exits
+pub fn chainload(path: &str, options: Option<&str>) -> BootResult<()> {
+    info!("Chainloading {}", path);
+
+    // has to outlive start_image, the child reads it from its LoadedImage
+    let options = options
+        .map(CString16::try_from)
+        .transpose()
+        .map_err(|_| uefi_error(uefi::Status::INVALID_PARAMETER))?;
+
+    let mut storage = vec::Vec::new();
+    let device_path = file_device_path(path, &mut storage)
+        .with_context(|| alloc::format!("failed to build a device path for {}", path))?;
+
+    let child = boot::load_image(
+        boot::image_handle(),
+        LoadImageSource::FromDevicePath {
+            device_path,
+            boot_policy: BootPolicy::ExactMatch,
+        },
+    )
+    .with_context(|| alloc::format!("failed to load {}", path))?;
+
+    let result = set_load_options(child, options.as_ref()).and_then(|_| {
+        boot::start_image(child).with_context(|| alloc::format!("{} returned an error", path))
+    });
+    // a child that never ran, or failed to start, would stay resident until reset; one that
+    // ran and exited is already gone, so the unload may fail harmlessly
+    if result.is_err() {
+        let _ = boot::unload_image(child);
+    }
+    result
+}
+
+fn set_load_options(child: Handle, options: Option<&CString16>) -> BootResult<()> {
+    if let Some(options) = options {
+        let mut loaded_image = boot::open_protocol_exclusive::<LoadedImage>(child)?;
+        unsafe {
+            loaded_image.set_load_options(options.as_ptr().cast(), options.num_bytes() as u32)
+        };
+    }
+    Ok(())
+}
+
+// the loader's device path with `path` appended, so the child can find its own volume
+fn file_device_path<'a>(path: &str, storage: &'a mut vec::Vec<u8>) -> BootResult<&'a DevicePath> {
+    let device = boot::open_protocol_exclusive::<LoadedImage>(boot::image_handle())?
+        .device()
+        .ok_or(uefi_error(uefi::Status::NOT_FOUND))?;
+    let device_path = boot::open_protocol_exclusive::<DevicePath>(device)?;
+    let path_name =
+        CString16::try_from(path).map_err(|_| uefi_error(uefi::Status::INVALID_PARAMETER))?;
+
+    let mut builder = DevicePathBuilder::with_vec(storage);
+    for node in device_path.node_iter() {
+        builder = builder
+            .push(&node)
+            .map_err(|_| uefi_error(uefi::Status::BUFFER_TOO_SMALL))?;
+    }
+    builder
+        .push(&build::media::FilePath {
+            path_name: &path_name,
+        })
+        .and_then(|builder| builder.finalize())
+        .map_err(|_| uefi_error(uefi::Status::BUFFER_TOO_SMALL))
+}
@@ -0,0 +1,315 @@
+use crate::info;
+use crate::util::*;
+
+extern crate alloc;
+use alloc::vec;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const LZ4_FRAME_MAGIC: [u8; 4] = [0x04, 0x22, 0x4d, 0x18];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+impl Compression {
+    fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(&ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else if bytes.starts_with(&LZ4_FRAME_MAGIC) {
+            Some(Compression::Lz4)
+        } else {
+            None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Lz4 => "lz4",
+        }
+    }
+}
+
+// images can be gzip, zstd or LZ4 frame compressed; anything else is passed through as is
+pub fn decompress_image(path: &str, bytes: vec::Vec<u8>) -> BootResult<vec::Vec<u8>> {
+    let Some(compression) = Compression::detect(&bytes) else {
+        return Ok(bytes);
+    };
+
+    let decompressed = match compression {
+        Compression::Gzip => gzip::decompress(&bytes),
+        Compression::Zstd => zstd::decompress(&bytes),
+        Compression::Lz4 => lz4::decompress(&bytes),
+    }
+    .map_err(|reason| BootError::Decompression {
+        format: compression.name(),
+        reason,
+    })?;
+
+    info!(
+        "Decompressed {} ({}): {} -> {} bytes",
+        path,
+        compression.name(),
+        bytes.len(),
+        decompressed.len()
+    );
+    Ok(decompressed)
+}
+
+#[cfg(feature = "gzip")]
+mod gzip {
+    extern crate alloc;
+    use alloc::vec;
+
+    const FLAG_HCRC: u8 = 1 << 1;
+    const FLAG_EXTRA: u8 = 1 << 2;
+    const FLAG_NAME: u8 = 1 << 3;
+    const FLAG_COMMENT: u8 = 1 << 4;
+    const METHOD_DEFLATE: u8 = 8;
+
+    // RFC 1952: header, raw deflate stream, then CRC32 and size of the original data
+    pub fn decompress(bytes: &[u8]) -> Result<vec::Vec<u8>, &'static str> {
+        let truncated = "truncated gzip stream";
+        if bytes.len() < 18 || bytes[2] != METHOD_DEFLATE {
+            return Err("not a deflate gzip stream");
+        }
+
+        let flags = bytes[3];
+        let mut offset = 10;
+        if flags & FLAG_EXTRA != 0 {
+            let length = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as usize;
+            offset += 2 + length;
+        }
+        for flag in [FLAG_NAME, FLAG_COMMENT] {
+            if flags & flag != 0 {
+                let end = bytes
+                    .get(offset..)
+                    .and_then(|rest| rest.iter().position(|byte| *byte == 0))
+                    .ok_or(truncated)?;
+                offset += end + 1;
+            }
+        }
+        if flags & FLAG_HCRC != 0 {
+            offset += 2;
+        }
+        if offset + 8 > bytes.len() {
+            return Err(truncated);
+        }
+
+        let (stream, trailer) = bytes[offset..].split_at(bytes.len() - offset - 8);
+        let crc = u32::from_le_bytes(trailer[0..4].try_into().unwrap());
+        let size = u32::from_le_bytes(trailer[4..8].try_into().unwrap());
+
+        // ISIZE bounds the output, a stream that inflates past it is rejected before it's all in memory
+        let data = miniz_oxide::inflate::decompress_to_vec_with_limit(stream, size as usize)
+            .map_err(|_| "corrupt deflate stream or size mismatch")?;
+        if data.len() as u32 != size {
+            return Err("size mismatch");
+        }
+
+        let actual = uefi::boot::calculate_crc32(&data).map_err(|_| "CRC check failed")?;
+        if actual != crc {
+            return Err("CRC mismatch");
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(feature = "zstd")]
+mod zstd {
+    extern crate alloc;
+    use alloc::vec;
+
+    use ruzstd::decoding::StreamingDecoder;
+    use ruzstd::io::Read;
+
+    // frames without a content size, or claiming more, are cut off here
+    const OUTPUT_MAX: u64 = 1 << 30;
+
+    pub fn decompress(mut bytes: &[u8]) -> Result<vec::Vec<u8>, &'static str> {
+        let decoder = StreamingDecoder::new(&mut bytes).map_err(|_| "invalid zstd frame header")?;
+        let limit = match decoder.decoder.content_size() {
+            0 => OUTPUT_MAX,
+            size => size.min(OUTPUT_MAX),
+        };
+
+        // one byte past the limit tells an oversized frame from one that fits exactly
+        let mut data = vec::Vec::new();
+        decoder
+            .take(limit + 1)
+            .read_to_end(&mut data)
+            .map_err(|_| "corrupt zstd stream")?;
+        if data.len() as u64 > limit {
+            return Err("zstd output exceeds the frame content size");
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(feature = "lz4")]
+mod lz4 {
+    extern crate alloc;
+    use alloc::vec;
+
+    const FLAG_VERSION_MASK: u8 = 0b1100_0000;
+    const FLAG_VERSION_1: u8 = 0b0100_0000;
+    const FLAG_BLOCK_CHECKSUM: u8 = 1 << 4;
+    const FLAG_CONTENT_SIZE: u8 = 1 << 3;
+    const FLAG_CONTENT_CHECKSUM: u8 = 1 << 2;
+    const FLAG_DICTIONARY_ID: u8 = 1 << 0;
+    const BLOCK_UNCOMPRESSED: u32 = 1 << 31;
+    const MIN_MATCH: usize = 4;
+
+    const TRUNCATED: &str = "truncated LZ4 frame";
+
+    fn le32(bytes: &[u8], offset: usize) -> Result<u32, &'static str> {
+        bytes
+            .get(offset..offset + 4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .ok_or(TRUNCATED)
+    }
+
+    // 15 in the token means more length bytes follow, until one isn't 255
+    fn extended_length(
+        block: &[u8],
+        offset: &mut usize,
+        mut length: usize,
+    ) -> Result<usize, &'static str> {
+        if length != 15 {
+            return Ok(length);
+        }
+        loop {
+            let byte = *block.get(*offset).ok_or(TRUNCATED)?;
+            *offset += 1;
+            length += byte as usize;
+            if byte != 255 {
+                return Ok(length);
+            }
+        }
+    }
+
+    // matches may reach back into earlier blocks of the frame, so decode straight into `output`
+    fn decompress_block(block: &[u8], output: &mut vec::Vec<u8>) -> Result<(), &'static str> {
+        let mut offset = 0;
+        while offset < block.len() {
+            let token = block[offset];
+            offset += 1;
+
+            let literals = extended_length(block, &mut offset, (token >> 4) as usize)?;
+            output.extend_from_slice(block.get(offset..offset + literals).ok_or(TRUNCATED)?);
+            offset += literals;
+
+            // the last sequence is literals only
+            if offset == block.len() {
+                break;
+            }
+
+            let distance = block
+                .get(offset..offset + 2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+                .ok_or(TRUNCATED)?;
+            offset += 2;
+            if distance == 0 || distance > output.len() {
+                return Err("LZ4 match offset out of range");
+            }
+
+            let length = extended_length(block, &mut offset, (token & 0xf) as usize)? + MIN_MATCH;
+            // overlapping copies repeat the pattern, so go byte by byte
+            let start = output.len() - distance;
+            for index in start..start + length {
+                output.push(output[index]);
+            }
+        }
+        Ok(())
+    }
+
+    // one or more concatenated frames; checksums are skipped, not verified
+    pub fn decompress(mut bytes: &[u8]) -> Result<vec::Vec<u8>, &'static str> {
+        let mut output = vec::Vec::new();
+
+        while !bytes.is_empty() {
+            if le32(bytes, 0)? != u32::from_le_bytes(super::LZ4_FRAME_MAGIC) {
+                return Err("bad LZ4 frame magic");
+            }
+            let flags = *bytes.get(4).ok_or(TRUNCATED)?;
+            if flags & FLAG_VERSION_MASK != FLAG_VERSION_1 {
+                return Err("unsupported LZ4 frame version");
+            }
+            if flags & FLAG_DICTIONARY_ID != 0 {
+                return Err("LZ4 dictionaries are not supported");
+            }
+
+            // magic, FLG, BD, optional content size and dictionary id, header checksum
+            let mut offset = 7;
+            if flags & FLAG_CONTENT_SIZE != 0 {
+                offset += 8;
+            }
+
+            loop {
+                let header = le32(bytes, offset)?;
+                offset += 4;
+                if header == 0 {
+                    break;
+                }
+
+                let size = (header & !BLOCK_UNCOMPRESSED) as usize;
+                let block = bytes.get(offset..offset + size).ok_or(TRUNCATED)?;
+                if header & BLOCK_UNCOMPRESSED != 0 {
+                    output.extend_from_slice(block);
+                } else {
+                    decompress_block(block, &mut output)?;
+                }
+
+                offset += size;
+                if flags & FLAG_BLOCK_CHECKSUM != 0 {
+                    offset += 4;
+                }
+            }
+
+            if flags & FLAG_CONTENT_CHECKSUM != 0 {
+                offset += 4;
+            }
+            bytes = bytes.get(offset..).ok_or(TRUNCATED)?;
+        }
+
+        Ok(output)
+    }
+}
+
+// without the feature, a compressed image is an error rather than a garbled ELF
+#[cfg(not(feature = "gzip"))]
+mod gzip {
+    extern crate alloc;
+    use alloc::vec;
+
+    pub fn decompress(_bytes: &[u8]) -> Result<vec::Vec<u8>, &'static str> {
+        Err("built without the `gzip` feature")
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+mod zstd {
+    extern crate alloc;
+    use alloc::vec;
+
+    pub fn decompress(_bytes: &[u8]) -> Result<vec::Vec<u8>, &'static str> {
+        Err("built without the `zstd` feature")
+    }
+}
+
+#[cfg(not(feature = "lz4"))]
+mod lz4 {
+    extern crate alloc;
+    use alloc::vec;
+
+    pub fn decompress(_bytes: &[u8]) -> Result<vec::Vec<u8>, &'static str> {
+        Err("built without the `lz4` feature")
+    }
+}
@@ -1,18 +1,314 @@
 use crate::{debug, error, info};
 
-use crate::util::BootResult;
-use crate::util::uefi_error;
+use crate::util::*;
+
+extern crate alloc;
+use alloc::string::ToString;
+use alloc::vec;
 
-use uefi::Status;
 use xmas_elf::ElfFile;
+use xmas_elf::header::HeaderPt2;
+use xmas_elf::program::{ProgramHeader, Type as ProgramHeaderType};
 use xmas_elf::sections::{SectionData, SectionHeader, ShType};
 use xmas_elf::symbol_table::Entry;
 
+const ELF_CLASS_64: u8 = 2;
+const ELF_DATA_LITTLE_ENDIAN: u8 = 1;
+const ELF_VERSION_CURRENT: u32 = 1;
+const ELF_MACHINE_X86_64: u16 = 0x3e;
+// static-pie images come out as ET_DYN; they are loaded at their link addresses all the same
+const ELF_TYPE_EXECUTABLE: u16 = 2;
+const ELF_TYPE_SHARED_OBJECT: u16 = 3;
+const ELF64_PROGRAM_HEADER_SIZE: usize = 56;
+const ELF64_SECTION_HEADER_SIZE: usize = 64;
+const ELF64_SYMBOL_SIZE: usize = 24;
+
+// parsed and validated; nothing is allocated for an image that fails here
 pub fn parse_elf(bytes: &[u8]) -> BootResult<ElfFile<'_>> {
-    xmas_elf::ElfFile::new(bytes).map_err(|e| {
-        error!("Failed to parse ELF file: {}", e);
-        uefi_error(Status::LOAD_ERROR)
-    })
+    xmas_elf::ElfFile::new(bytes)
+        .map_err(|reason| {
+            error!("Failed to parse ELF file: {}", reason);
+            BootError::ElfParse { reason }
+        })
+        .and_then(|elf| {
+            validate_elf(&elf, bytes.len())
+                .inspect_err(|e| error!("Rejecting ELF file: {}", e))
+                .map(|_| elf)
+        })
+}
+
+// everything the loaders below rely on, so they can slice and copy without checking again
+fn validate_elf(elf: &ElfFile, file_size: usize) -> BootResult<()> {
+    // e_ident[EI_CLASS], e_ident[EI_DATA]
+    let (class, data) = (elf.input[4], elf.input[5]);
+    let HeaderPt2::Header64(header) = elf.header.pt2 else {
+        return Err(ElfViolation::Class(class).into());
+    };
+    if class != ELF_CLASS_64 {
+        return Err(ElfViolation::Class(class).into());
+    }
+    if data != ELF_DATA_LITTLE_ENDIAN {
+        return Err(ElfViolation::Endianness(data).into());
+    }
+    if header.version != ELF_VERSION_CURRENT {
+        return Err(ElfViolation::Version(header.version).into());
+    }
+    // e_machine
+    let machine = u16::from_le_bytes([elf.input[18], elf.input[19]]);
+    if machine != ELF_MACHINE_X86_64 {
+        return Err(ElfViolation::Machine(machine).into());
+    }
+    if ![ELF_TYPE_EXECUTABLE, ELF_TYPE_SHARED_OBJECT].contains(&header.type_.0) {
+        return Err(ElfViolation::FileType(header.type_.0).into());
+    }
+
+    check_header_table(
+        "program",
+        header.ph_offset as usize,
+        header.ph_entry_size as usize,
+        header.ph_count as usize,
+        ELF64_PROGRAM_HEADER_SIZE,
+        file_size,
+    )?;
+    check_header_table(
+        "section",
+        header.sh_offset as usize,
+        header.sh_entry_size as usize,
+        header.sh_count as usize,
+        ELF64_SECTION_HEADER_SIZE,
+        file_size,
+    )?;
+
+    let segments = elf
+        .program_iter()
+        .enumerate()
+        .filter(|(_, program_header)| program_header.get_type() == Ok(ProgramHeaderType::Load))
+        .collect::<vec::Vec<_>>();
+    if segments.is_empty() {
+        return Err(ElfViolation::NoLoadSegments.into());
+    }
+
+    for (index, program_header) in &segments {
+        check_segment(*index, program_header, file_size)?;
+    }
+    check_segments_disjoint(&segments)?;
+
+    for (index, section_header) in elf.section_iter().enumerate() {
+        check_section(elf, index, &section_header, file_size)?;
+    }
+
+    let entry = header.entry_point as usize;
+    let entry_in_code = segments.iter().any(|(_, program_header)| {
+        let start = program_header.virtual_addr() as usize;
+        program_header.flags().is_execute()
+            && (start..start + program_header.mem_size() as usize).contains(&entry)
+    });
+    if !entry_in_code {
+        return Err(ElfViolation::EntryPointNotExecutable { entry }.into());
+    }
+
+    Ok(())
+}
+
+// a table with no entries is fine wherever it claims to be
+fn check_header_table(
+    table: &'static str,
+    offset: usize,
+    entry_size: usize,
+    count: usize,
+    expected_entry_size: usize,
+    file_size: usize,
+) -> Result<(), ElfViolation> {
+    let fits = count == 0
+        || (entry_size == expected_entry_size
+            && offset
+                .checked_add(count * entry_size)
+                .is_some_and(|end| end <= file_size));
+    if fits {
+        Ok(())
+    } else {
+        Err(ElfViolation::HeaderTable {
+            table,
+            offset,
+            entry_size,
+            count,
+            file_size,
+        })
+    }
+}
+
+// xmas-elf slices section data (and asserts on symbol table layout) without checking
+fn check_section(
+    elf: &ElfFile,
+    index: usize,
+    section_header: &SectionHeader,
+    file_size: usize,
+) -> Result<(), ElfViolation> {
+    let section_type = section_header.get_type();
+    let offset = section_header.offset() as usize;
+    let size = section_header.size() as usize;
+    let link = section_header.link() as usize;
+
+    if section_type != Ok(ShType::NoBits)
+        && offset.checked_add(size).is_none_or(|end| end > file_size)
+    {
+        return Err(ElfViolation::SectionOutsideFile {
+            index,
+            offset,
+            size,
+            file_size,
+        });
+    }
+    if link >= elf.header.pt2.sh_count() as usize {
+        return Err(ElfViolation::SectionLink { index, link });
+    }
+    if section_type == Ok(ShType::SymTab) {
+        let linked_type = elf
+            .section_header(link as u16)
+            .ok()
+            .map(|linked| linked.get_type());
+        if linked_type != Some(Ok(ShType::StrTab)) {
+            return Err(ElfViolation::SectionLink { index, link });
+        }
+        if !offset.is_multiple_of(8) || !size.is_multiple_of(ELF64_SYMBOL_SIZE) {
+            return Err(ElfViolation::SymbolTableLayout { index });
+        }
+    }
+
+    Ok(())
+}
+
+fn check_segment(
+    index: usize,
+    program_header: &ProgramHeader,
+    file_size: usize,
+) -> Result<(), ElfViolation> {
+    let offset = program_header.offset() as usize;
+    let size = program_header.file_size() as usize;
+    let memory_size = program_header.mem_size() as usize;
+    let address = program_header.virtual_addr() as usize;
+    let align = program_header.align() as usize;
+
+    if offset.checked_add(size).is_none_or(|end| end > file_size) {
+        return Err(ElfViolation::SegmentOutsideFile {
+            index,
+            offset,
+            size,
+            file_size,
+        });
+    }
+    if size > memory_size {
+        return Err(ElfViolation::FileSizeExceedsMemorySize {
+            index,
+            file_size: size,
+            memory_size,
+        });
+    }
+    // 0 and 1 both mean no alignment requirement
+    if align > 1 && !align.is_power_of_two() {
+        return Err(ElfViolation::Alignment { index, align });
+    }
+    if align > 1 && address % align != offset % align {
+        return Err(ElfViolation::Misaligned {
+            index,
+            address,
+            offset,
+            align,
+        });
+    }
+    if address.checked_add(memory_size).is_none()
+        || (program_header.physical_addr() as usize)
+            .checked_add(memory_size)
+            .is_none()
+    {
+        return Err(ElfViolation::AddressOverflow { index });
+    }
+
+    Ok(())
+}
+
+// no two PT_LOADs may claim the same virtual or physical bytes
+fn check_segments_disjoint(segments: &[(usize, ProgramHeader)]) -> BootResult<()> {
+    let ranges = |address: fn(&ProgramHeader) -> u64| {
+        segments
+            .iter()
+            .map(|(_, program_header)| {
+                let start = address(program_header) as usize;
+                (start, start + program_header.mem_size() as usize)
+            })
+            .filter(|(start, end)| start != end)
+            .collect::<vec::Vec<_>>()
+    };
+
+    for ranges in [
+        ranges(|program_header| program_header.virtual_addr()),
+        ranges(|program_header| program_header.physical_addr()),
+    ] {
+        for (index, first) in ranges.iter().enumerate() {
+            if let Some(second) = ranges[index + 1..]
+                .iter()
+                .find(|second| first.0 < second.1 && second.0 < first.1)
+            {
+                return Err(BootError::SegmentOverlap {
+                    first: *first,
+                    second: *second,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+// descriptor of the first note called `name` with type `note_type`, from PT_NOTE segments or
+// SHT_NOTE sections; each note is namesz, descsz, type, then name and desc padded to 4 bytes
+pub fn find_note<'a>(elf: &ElfFile<'a>, name: &str, note_type: u32) -> Option<&'a [u8]> {
+    let segments = elf
+        .program_iter()
+        .filter(|program_header| program_header.get_type() == Ok(ProgramHeaderType::Note))
+        .map(|program_header| {
+            (
+                program_header.offset() as usize,
+                program_header.file_size() as usize,
+            )
+        });
+    let sections = elf
+        .section_iter()
+        .filter(|section_header| section_header.get_type() == Ok(ShType::Note))
+        .map(|section_header| {
+            (
+                section_header.offset() as usize,
+                section_header.size() as usize,
+            )
+        });
+
+    segments
+        .chain(sections)
+        .filter_map(|(offset, size)| elf.input.get(offset..offset.checked_add(size)?))
+        .find_map(|notes| find_note_in(notes, name, note_type))
+}
+
+fn find_note_in<'a>(mut notes: &'a [u8], name: &str, note_type: u32) -> Option<&'a [u8]> {
+    let word = |bytes: &[u8], offset: usize| {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+    };
+
+    while notes.len() >= 12 {
+        let name_end = 12 + word(notes, 0);
+        let description_start = align_up(name_end, 4);
+        let description_end = description_start + word(notes, 4);
+
+        // the name is stored with its terminating NUL
+        let note_name = notes.get(12..name_end)?;
+        let description = notes.get(description_start..description_end)?;
+        if word(notes, 8) == note_type as usize
+            && note_name.strip_suffix(&[0]).unwrap_or(note_name) == name.as_bytes()
+        {
+            return Some(description);
+        }
+
+        notes = notes.get(align_up(description_end, 4)..)?;
+    }
+    None
 }
 
 pub fn find_address_from_symbol_name(elf: &ElfFile, symbol_name: &str) -> BootResult<usize> {
@@ -53,7 +349,9 @@ pub fn find_address_from_symbol_name(elf: &ElfFile, symbol_name: &str) -> BootRe
 
     debug!("Symbol '{}' not found in any symbol table", symbol_name);
     error!("Failed to read symbol table");
-    Err(uefi_error(Status::NOT_FOUND))
+    Err(BootError::SymbolMissing {
+        name: symbol_name.to_string(),
+    })
 }
 
 fn lookup_string_table<'a>(
@@ -0,0 +1,396 @@
+use crate::util::*;
+
+extern crate alloc;
+use alloc::string::{String, ToString};
+use alloc::vec;
+
+use uefi::Handle;
+use uefi::boot::{self, OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol};
+use uefi::proto::media::block::BlockIO;
+use uefi::proto::media::disk::DiskIo;
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const EXT2_MAGIC: u16 = 0xef53;
+const ROOT_INODE: u32 = 2;
+
+// s_feature_incompat
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const INCOMPAT_EXTENTS: u32 = 0x0040;
+const INCOMPAT_64BIT: u32 = 0x0080;
+const INCOMPAT_FLEX_BG: u32 = 0x0200;
+// anything else changes the on-disk layout in ways we can't read
+const INCOMPAT_SUPPORTED: u32 =
+    INCOMPAT_FILETYPE | INCOMPAT_EXTENTS | INCOMPAT_64BIT | INCOMPAT_FLEX_BG;
+
+// i_flags
+const INODE_EXTENTS_FLAG: u32 = 0x0008_0000;
+const EXTENT_MAGIC: u16 = 0xf30a;
+// ext4 never builds deeper trees; bounds the recursion on corrupt or cyclic ones
+const EXTENT_MAX_DEPTH: u16 = 5;
+const EXTENT_ENTRY_SIZE: usize = 12;
+
+// i_mode
+const MODE_TYPE_MASK: u16 = 0xf000;
+const MODE_DIRECTORY: u16 = 0x4000;
+const MODE_REGULAR: u16 = 0x8000;
+
+fn le16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+#[derive(Debug, Clone)]
+struct Inode {
+    mode: u16,
+    size: u64,
+    flags: u32,
+    // i_block: 12 direct, indirect, double, triple; or an extent tree
+    block: [u8; 60],
+}
+
+impl Inode {
+    fn is_directory(&self) -> bool {
+        self.mode & MODE_TYPE_MASK == MODE_DIRECTORY
+    }
+
+    fn is_regular(&self) -> bool {
+        self.mode & MODE_TYPE_MASK == MODE_REGULAR
+    }
+}
+
+// read-only ext2 (and extent-based ext4 without journal replay) over DiskIo
+pub struct Ext2 {
+    disk: ScopedProtocol<DiskIo>,
+    media_id: u32,
+    block_size: usize,
+    blocks_count: u64,
+    inodes_per_group: u32,
+    inode_size: usize,
+    group_descriptor_size: usize,
+    // block holding the first group descriptor
+    group_table_block: u64,
+    incompat: u32,
+    label: String,
+}
+
+impl Ext2 {
+    // Ok(None) if the volume doesn't hold an ext2 file system
+    pub fn open(handle: Handle) -> BootResult<Option<Self>> {
+        let params = OpenProtocolParams {
+            handle,
+            agent: boot::image_handle(),
+            controller: None,
+        };
+        let media_id =
+            unsafe { boot::open_protocol::<BlockIO>(params, OpenProtocolAttributes::GetProtocol) }
+                .map(|block_io| block_io.media().media_id())?;
+        let disk =
+            unsafe { boot::open_protocol::<DiskIo>(params, OpenProtocolAttributes::GetProtocol) }?;
+
+        let mut superblock = [0u8; SUPERBLOCK_SIZE];
+        disk.read_disk(media_id, SUPERBLOCK_OFFSET, &mut superblock)?;
+        if le16(&superblock, 56) != EXT2_MAGIC {
+            return Ok(None);
+        }
+
+        let incompat = le32(&superblock, 96);
+        if incompat & !INCOMPAT_SUPPORTED != 0 {
+            return Err(BootError::FileSystem {
+                reason: "unsupported ext2 incompat features",
+            });
+        }
+
+        let log_block_size = le32(&superblock, 24);
+        if log_block_size > 6 {
+            return Err(BootError::FileSystem {
+                reason: "invalid ext2 block size",
+            });
+        }
+        let block_size = 1024usize << log_block_size;
+
+        // revision 0 has fixed 128 byte inodes
+        let inode_size = match le32(&superblock, 76) {
+            0 => 128,
+            _ => le16(&superblock, 88) as usize,
+        };
+        let group_descriptor_size = match incompat & INCOMPAT_64BIT {
+            0 => 32,
+            _ => (le16(&superblock, 254) as usize).max(32),
+        };
+        let inodes_per_group = le32(&superblock, 40);
+        if inode_size < 128 || inodes_per_group == 0 {
+            return Err(BootError::FileSystem {
+                reason: "invalid ext2 superblock",
+            });
+        }
+
+        let mut blocks_count = le32(&superblock, 4) as u64;
+        if incompat & INCOMPAT_64BIT != 0 {
+            blocks_count |= (le32(&superblock, 0x150) as u64) << 32;
+        }
+
+        let label = superblock[120..136]
+            .iter()
+            .take_while(|byte| **byte != 0)
+            .map(|byte| *byte as char)
+            .collect();
+
+        Ok(Some(Ext2 {
+            disk,
+            media_id,
+            block_size,
+            blocks_count,
+            inodes_per_group,
+            inode_size,
+            group_descriptor_size,
+            // the table follows the block holding the superblock
+            group_table_block: le32(&superblock, 20) as u64 + 1,
+            incompat,
+            label,
+        }))
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn read_bytes(&self, offset: u64, buffer: &mut [u8]) -> BootResult<()> {
+        self.disk
+            .read_disk(self.media_id, offset, buffer)
+            .map_err(BootError::from)
+    }
+
+    fn read_block(&self, block: u64, buffer: &mut [u8]) -> BootResult<()> {
+        // block 0 is a hole in sparse files
+        if block == 0 {
+            buffer.fill(0);
+            return Ok(());
+        }
+        self.read_bytes(block * self.block_size as u64, buffer)
+    }
+
+    fn inode(&self, number: u32) -> BootResult<Inode> {
+        if number == 0 {
+            return Err(BootError::FileSystem {
+                reason: "invalid inode number",
+            });
+        }
+
+        let group = ((number - 1) / self.inodes_per_group) as u64;
+        let index = ((number - 1) % self.inodes_per_group) as u64;
+
+        let mut descriptor = vec![0u8; self.group_descriptor_size];
+        self.read_bytes(
+            self.group_table_block * self.block_size as u64
+                + group * self.group_descriptor_size as u64,
+            &mut descriptor,
+        )?;
+        let mut inode_table = le32(&descriptor, 8) as u64;
+        if self.incompat & INCOMPAT_64BIT != 0 && self.group_descriptor_size >= 64 {
+            inode_table |= (le32(&descriptor, 0x28) as u64) << 32;
+        }
+
+        let mut raw = [0u8; 128];
+        self.read_bytes(
+            inode_table * self.block_size as u64 + index * self.inode_size as u64,
+            &mut raw,
+        )?;
+
+        let mut block = [0u8; 60];
+        block.copy_from_slice(&raw[40..100]);
+        Ok(Inode {
+            mode: le16(&raw, 0),
+            size: le32(&raw, 4) as u64 | (le32(&raw, 108) as u64) << 32,
+            flags: le32(&raw, 32),
+            block,
+        })
+    }
+
+    // physical block of logical block `index`, 0 for holes
+    fn file_block(&self, inode: &Inode, index: u64) -> BootResult<u64> {
+        if inode.flags & INODE_EXTENTS_FLAG != 0 {
+            return self.extent_block(&inode.block, index, EXTENT_MAX_DEPTH);
+        }
+
+        let pointers = (self.block_size / 4) as u64;
+        let direct = |slot: usize| le32(&inode.block, slot * 4) as u64;
+
+        if index < 12 {
+            return Ok(direct(index as usize));
+        }
+
+        // single, double and triple indirect ranges follow the direct blocks
+        let mut index = index - 12;
+        let mut span = 1u64;
+        for (depth, slot) in [(1u32, 12usize), (2, 13), (3, 14)] {
+            span *= pointers;
+            if index < span {
+                return self.indirect_block(direct(slot), depth, index);
+            }
+            index -= span;
+        }
+
+        Err(BootError::FileSystem {
+            reason: "file block out of range",
+        })
+    }
+
+    fn indirect_block(&self, mut block: u64, depth: u32, index: u64) -> BootResult<u64> {
+        let pointers = (self.block_size / 4) as u64;
+        let mut table = vec![0u8; self.block_size];
+
+        for level in (0..depth).rev() {
+            if block == 0 {
+                return Ok(0);
+            }
+            self.read_block(block, &mut table)?;
+            let slot = (index / pointers.pow(level)) % pointers;
+            block = le32(&table, slot as usize * 4) as u64;
+        }
+        Ok(block)
+    }
+
+    // `node` is an extent header followed by index or leaf entries (12 bytes each); every
+    // level down must be exactly one shallower, so a cyclic tree can't recurse forever
+    fn extent_block(&self, node: &[u8], index: u64, max_depth: u16) -> BootResult<u64> {
+        if le16(node, 0) != EXTENT_MAGIC {
+            return Err(BootError::FileSystem {
+                reason: "bad extent header",
+            });
+        }
+        let entries = le16(node, 2) as usize;
+        let depth = le16(node, 6);
+        if depth > max_depth || EXTENT_ENTRY_SIZE + entries * EXTENT_ENTRY_SIZE > node.len() {
+            return Err(BootError::FileSystem {
+                reason: "corrupt extent tree",
+            });
+        }
+
+        if depth == 0 {
+            for entry in (0..entries).map(|i| &node[12 + i * 12..24 + i * 12]) {
+                let first = le32(entry, 0) as u64;
+                let length = le16(entry, 4) as u64;
+                let start = (le16(entry, 6) as u64) << 32 | le32(entry, 8) as u64;
+                // lengths above 32768 mark uninitialized extents, which read as zeros
+                let (length, initialized) = match length {
+                    0..=32768 => (length, true),
+                    _ => (length - 32768, false),
+                };
+                if (first..first + length).contains(&index) {
+                    return Ok(if initialized {
+                        start + index - first
+                    } else {
+                        0
+                    });
+                }
+            }
+            return Ok(0);
+        }
+
+        // last index entry starting at or before `index`
+        let child = (0..entries)
+            .map(|i| &node[12 + i * 12..24 + i * 12])
+            .take_while(|entry| le32(entry, 0) as u64 <= index)
+            .last();
+        match child {
+            Some(entry) => {
+                let leaf = (le16(entry, 8) as u64) << 32 | le32(entry, 4) as u64;
+                let mut block = vec![0u8; self.block_size];
+                self.read_block(leaf, &mut block)?;
+                self.extent_block(&block, index, depth - 1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn read_inode_data(&self, inode: &Inode) -> BootResult<vec::Vec<u8>> {
+        // i_size comes straight from disk, don't let it decide the allocation alone
+        if inode.size > self.blocks_count.saturating_mul(self.block_size as u64) {
+            return Err(BootError::FileSystem {
+                reason: "inode size exceeds the file system",
+            });
+        }
+        let size = inode.size as usize;
+        let length = size.div_ceil(self.block_size) * self.block_size;
+        let mut data = vec::Vec::new();
+        data.try_reserve_exact(length)
+            .map_err(|_| BootError::FileSystem {
+                reason: "file too large to read into memory",
+            })?;
+        data.resize(length, 0);
+
+        for (index, chunk) in data.chunks_mut(self.block_size).enumerate() {
+            let block = self.file_block(inode, index as u64)?;
+            self.read_block(block, chunk)?;
+        }
+
+        data.truncate(size);
+        Ok(data)
+    }
+
+    fn find_entry(&self, directory: &Inode, name: &str) -> BootResult<Option<u32>> {
+        let data = self.read_inode_data(directory)?;
+        let mut offset = 0;
+
+        while offset + 8 <= data.len() {
+            let inode = le32(&data, offset);
+            let record_length = le16(&data, offset + 4) as usize;
+            // without the filetype feature the length is 16 bits
+            let name_length = match self.incompat & INCOMPAT_FILETYPE {
+                0 => le16(&data, offset + 6) as usize,
+                _ => data[offset + 6] as usize,
+            };
+            if record_length < 8 || offset + 8 + name_length > data.len() {
+                break;
+            }
+
+            if inode != 0 && &data[offset + 8..offset + 8 + name_length] == name.as_bytes() {
+                return Ok(Some(inode));
+            }
+            offset += record_length;
+        }
+        Ok(None)
+    }
+
+    // UEFI style (`\kernel\kernel.elf`) or unix style paths from the root
+    fn lookup(&self, path: &str) -> BootResult<Option<Inode>> {
+        let mut inode = self.inode(ROOT_INODE)?;
+
+        for component in path.split(['\\', '/']).filter(|c| !c.is_empty()) {
+            if !inode.is_directory() {
+                return Ok(None);
+            }
+            match self.find_entry(&inode, component)? {
+                Some(number) => inode = self.inode(number)?,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(inode))
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        matches!(self.lookup(path), Ok(Some(_)))
+    }
+
+    pub fn read_file(&self, path: &str) -> BootResult<vec::Vec<u8>> {
+        match self.lookup(path)? {
+            Some(inode) if inode.is_regular() => self.read_inode_data(&inode),
+            Some(_) => Err(BootError::FileRead {
+                path: path.to_string(),
+                status: uefi::Status::INVALID_PARAMETER,
+            }),
+            None => Err(BootError::FileNotFound {
+                path: path.to_string(),
+            }),
+        }
+    }
+}
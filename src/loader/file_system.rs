@@ -1,29 +1,95 @@
 use crate::info;
 
 extern crate alloc;
+use alloc::string::ToString;
 use alloc::vec;
 
+use uefi::boot::{self, OpenProtocolAttributes, OpenProtocolParams};
 use uefi::fs::FileSystem;
-use uefi::{CString16, boot};
+use uefi::proto::media::fs::SimpleFileSystem;
+use uefi::{CString16, Handle};
 
-use crate::util::BootResult;
+use crate::util::*;
 
 pub fn read_entire_file(filepath: &str) -> BootResult<vec::Vec<u8>> {
+    open_file_system().and_then(|target_fs| read_entire_file_in(target_fs, filepath))
+}
+
+// same as read_entire_file, on another volume
+pub fn read_entire_file_from(volume: Handle, filepath: &str) -> BootResult<vec::Vec<u8>> {
+    open_volume(volume).and_then(|target_fs| read_entire_file_in(target_fs, filepath))
+}
+
+fn read_entire_file_in(mut target_fs: FileSystem, filepath: &str) -> BootResult<vec::Vec<u8>> {
     info!("Reading file: {}", filepath);
-    boot::get_image_file_system(boot::image_handle()).map(FileSystem::new)
-        .map_err(|_| crate::util::uefi_error(uefi::Status::INVALID_PARAMETER))
+    to_path(filepath).and_then(|path| {
+        let path = uefi::fs::Path::new(path.as_ref());
+        info_file_metadata(path, &mut target_fs)?;
+        target_fs
+            .read(path)
+            .map_err(|e| file_system_error(filepath, e))
+    })
+}
+
+fn open_file_system() -> BootResult<FileSystem> {
+    boot::get_image_file_system(boot::image_handle())
+        .map(FileSystem::new)
+        .context("failed to open the loader volume")
+}
+
+// shared, other volumes may well be in use by someone else
+pub fn open_volume(volume: Handle) -> BootResult<FileSystem> {
+    unsafe {
+        boot::open_protocol::<SimpleFileSystem>(
+            OpenProtocolParams {
+                handle: volume,
+                agent: boot::image_handle(),
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    }
+    .map(FileSystem::new)
+    .context("failed to open a file system volume")
+}
+
+pub fn file_exists(volume: Handle, filepath: &str) -> bool {
+    let Ok(path) = to_path(filepath) else {
+        return false;
+    };
+    open_volume(volume)
+        .ok()
         .and_then(|mut target_fs| {
-            CString16::try_from(filepath)
-                .map_err(|_| crate::util::uefi_error(uefi::Status::INVALID_PARAMETER))
-                // .map(move |cstr16| uefi::fs::Path::new(cstr16))
-                .and_then(|path| {
-                    let path = uefi::fs::Path::new(path.as_ref());
-                    info_file_metadata(path, &mut target_fs)?;
-                    target_fs
-                        .read(path)
-                        .map_err(|_| crate::util::uefi_error(uefi::Status::NOT_FOUND))
-                })
+            target_fs
+                .try_exists(uefi::fs::Path::new(path.as_ref()))
+                .ok()
         })
+        .unwrap_or(false)
+}
+
+fn to_path(filepath: &str) -> BootResult<CString16> {
+    CString16::try_from(filepath).map_err(|_| BootError::FileRead {
+        path: filepath.to_string(),
+        status: uefi::Status::INVALID_PARAMETER,
+    })
+}
+
+// keep NOT_FOUND apart from real I/O problems
+fn file_system_error(filepath: &str, error: uefi::fs::Error) -> BootError {
+    let status = match error {
+        uefi::fs::Error::Io(io) => io.uefi_error.status(),
+        _ => uefi::Status::INVALID_PARAMETER,
+    };
+
+    match status {
+        uefi::Status::NOT_FOUND => BootError::FileNotFound {
+            path: filepath.to_string(),
+        },
+        status => BootError::FileRead {
+            path: filepath.to_string(),
+            status,
+        },
+    }
 }
 
 pub fn info_file_metadata(
@@ -32,7 +98,7 @@ pub fn info_file_metadata(
 ) -> BootResult<()> {
     file_system
         .metadata(file_path)
-        .map_err(|_| crate::util::uefi_error(uefi::Status::NOT_FOUND))
+        .map_err(|e| file_system_error(&file_path.to_cstr16().to_string(), e))
         .map(|metadata| {
             info!(
                 "File: {}, Size: {} bytes, created: {}",
@@ -40,7 +106,6 @@ pub fn info_file_metadata(
                 metadata.file_size(),
                 metadata.create_time()
             );
-            
         })
 }
 
@@ -50,7 +115,7 @@ pub fn info_file_in_directory(
 ) -> BootResult<()> {
     file_system
         .read_dir(directory_path)
-        .map_err(|_| crate::util::uefi_error(uefi::Status::NOT_FOUND))
+        .map_err(|e| file_system_error(&directory_path.to_cstr16().to_string(), e))
         .map(|iter| {
             iter.filter_map(|entry| entry.ok()).for_each(|file_info| {
                 info!(
@@ -61,6 +126,33 @@ pub fn info_file_in_directory(
                     file_info.create_time()
                 );
             });
-            
         })
 }
+
+// no logging in here, the boot log file sink writes through these while the log is busy
+pub fn write_entire_file(filepath: &str, content: &[u8]) -> BootResult<()> {
+    open_file_system().and_then(|mut target_fs| {
+        to_path(filepath).and_then(|path| {
+            target_fs
+                .write(uefi::fs::Path::new(path.as_ref()), content)
+                .map_err(|e| file_system_error(filepath, e))
+        })
+    })
+}
+
+// replaces `to` if it exists; Ok(false) if there was no `from` to rename
+pub fn rename_file(from: &str, to: &str) -> BootResult<bool> {
+    open_file_system().and_then(|mut target_fs| {
+        let source = to_path(from)?;
+        let destination = to_path(to)?;
+        let source = uefi::fs::Path::new(source.as_ref());
+
+        match target_fs.try_exists(source) {
+            Ok(true) => target_fs
+                .rename(source, uefi::fs::Path::new(destination.as_ref()))
+                .map(|_| true)
+                .map_err(|e| file_system_error(from, e)),
+            _ => Ok(false),
+        }
+    })
+}
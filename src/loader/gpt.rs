@@ -0,0 +1,290 @@
+use crate::util::*;
+use crate::{debug, warn};
+
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec;
+
+use uefi::boot::{self, OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol};
+use uefi::proto::media::block::BlockIO;
+use uefi::proto::media::disk::DiskIo;
+use uefi::{Guid, Handle, guid};
+
+// partition type of an A9N system partition (kernel, init and modules)
+pub const A9N_SYSTEM_PARTITION_TYPE: Guid = guid!("5a9e4c1d-7b3f-4e8a-9c2d-a9b0c5e20002");
+
+// type specific attribute bits (48..64) of an A9N system partition
+pub const A9N_PARTITION_BOOTABLE: u64 = 1 << 48;
+const A9N_PARTITION_PRIORITY_SHIFT: u64 = 52;
+const A9N_PARTITION_PRIORITY_MASK: u64 = 0xf;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_MIN_SIZE: usize = 92;
+const GPT_ENTRY_MIN_SIZE: usize = 128;
+// the spec reserves at least 16 KiB for the entries; anything far beyond that is garbage
+const GPT_ENTRIES_MAX_BYTES: usize = 1024 * 1024;
+
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn le64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn guid_at(bytes: &[u8], offset: usize) -> Guid {
+    Guid::from_bytes(bytes[offset..offset + 16].try_into().unwrap())
+}
+
+#[derive(Debug, Clone)]
+pub struct GptPartition {
+    // the whole disk the table was read from
+    pub disk: Handle,
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    pub first_lba: u64,
+    pub last_lba: u64,
+    pub attributes: u64,
+    pub name: String,
+}
+
+impl GptPartition {
+    pub fn is_bootable(&self) -> bool {
+        self.attributes & A9N_PARTITION_BOOTABLE != 0
+    }
+
+    // 0..=15, higher is preferred
+    pub fn priority(&self) -> u8 {
+        ((self.attributes >> A9N_PARTITION_PRIORITY_SHIFT) & A9N_PARTITION_PRIORITY_MASK) as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GptHeader {
+    my_lba: u64,
+    alternate_lba: u64,
+    entries_lba: u64,
+    entry_count: usize,
+    entry_size: usize,
+    entries_crc: u32,
+}
+
+// DiskIo rather than BlockIO, so reads don't have to honour the media's io_align
+struct Disk {
+    disk: ScopedProtocol<DiskIo>,
+    media_id: u32,
+    block_size: usize,
+    last_lba: u64,
+}
+
+impl Disk {
+    fn read(&self, lba: u64, blocks: usize) -> BootResult<vec::Vec<u8>> {
+        let size = blocks
+            .checked_mul(self.block_size)
+            .ok_or(gpt_error("GPT read size overflow"))?;
+        let offset = lba
+            .checked_mul(self.block_size as u64)
+            .ok_or(gpt_error("GPT read offset overflow"))?;
+        let mut buffer = vec![0u8; size];
+        self.disk
+            .read_disk(self.media_id, offset, &mut buffer)
+            .map(|_| buffer)
+            .map_err(BootError::from)
+    }
+
+    fn last_lba(&self) -> u64 {
+        self.last_lba
+    }
+
+    fn header(&self, lba: u64) -> BootResult<GptHeader> {
+        let block = self.read(lba, 1)?;
+        if &block[0..8] != GPT_SIGNATURE {
+            return Err(gpt_error("missing GPT signature"));
+        }
+
+        let header_size = le32(&block, 12) as usize;
+        if !(GPT_HEADER_MIN_SIZE..=self.block_size).contains(&header_size) {
+            return Err(gpt_error("invalid GPT header size"));
+        }
+
+        // the CRC covers the header with its own CRC field zeroed
+        let mut header = block[..header_size].to_vec();
+        header[16..20].fill(0);
+        if boot::calculate_crc32(&header)? != le32(&block, 16) {
+            return Err(gpt_error("GPT header CRC mismatch"));
+        }
+
+        let parsed = GptHeader {
+            my_lba: le64(&block, 24),
+            alternate_lba: le64(&block, 32),
+            entries_lba: le64(&block, 72),
+            entry_count: le32(&block, 80) as usize,
+            entry_size: le32(&block, 84) as usize,
+            entries_crc: le32(&block, 88),
+        };
+        if parsed.my_lba != lba {
+            return Err(gpt_error("GPT header is not where it claims to be"));
+        }
+        if parsed.entry_size < GPT_ENTRY_MIN_SIZE
+            || !parsed.entry_size.is_power_of_two()
+            || parsed.entry_count * parsed.entry_size > GPT_ENTRIES_MAX_BYTES
+        {
+            return Err(gpt_error("invalid GPT partition entry array"));
+        }
+        Ok(parsed)
+    }
+
+    fn entries(&self, header: &GptHeader) -> BootResult<vec::Vec<u8>> {
+        let bytes = header.entry_count * header.entry_size;
+        let mut entries = self.read(header.entries_lba, bytes.div_ceil(self.block_size))?;
+        entries.truncate(bytes);
+
+        if boot::calculate_crc32(&entries)? != header.entries_crc {
+            return Err(gpt_error("GPT partition entry CRC mismatch"));
+        }
+        Ok(entries)
+    }
+
+    // header and entries at `lba`, both CRC checked
+    fn table(&self, lba: u64) -> BootResult<(GptHeader, vec::Vec<u8>)> {
+        self.header(lba)
+            .and_then(|header| self.entries(&header).map(|entries| (header, entries)))
+    }
+}
+
+fn gpt_error(reason: &'static str) -> BootError {
+    BootError::FileSystem { reason }
+}
+
+fn open_disk(handle: Handle) -> BootResult<Option<Disk>> {
+    let params = OpenProtocolParams {
+        handle,
+        agent: boot::image_handle(),
+        controller: None,
+    };
+    let block_io =
+        unsafe { boot::open_protocol::<BlockIO>(params, OpenProtocolAttributes::GetProtocol) }?;
+
+    // partitions have BlockIO as well, only whole disks carry a partition table
+    let media = block_io.media();
+    if media.is_logical_partition() || !media.is_media_present() {
+        return Ok(None);
+    }
+    let block_size = media.block_size() as usize;
+    if block_size < 512 {
+        return Ok(None);
+    }
+
+    let media_id = media.media_id();
+    let last_lba = media.last_block();
+    let disk =
+        unsafe { boot::open_protocol::<DiskIo>(params, OpenProtocolAttributes::GetProtocol) }?;
+
+    Ok(Some(Disk {
+        disk,
+        media_id,
+        block_size,
+        last_lba,
+    }))
+}
+
+// the primary table, or the backup at the end of the disk if the primary is damaged.
+// Ok(None) for disks without a GPT
+fn read_table(disk: &Disk) -> BootResult<Option<(GptHeader, vec::Vec<u8>)>> {
+    let primary = disk.table(1);
+    let backup_lba = match &primary {
+        Ok((header, _)) => header.alternate_lba,
+        Err(_) => disk.last_lba(),
+    };
+    let backup = disk.table(backup_lba);
+
+    match (primary, backup) {
+        (Ok(primary), Ok(_)) => Ok(Some(primary)),
+        (Ok(primary), Err(e)) => {
+            warn!("GPT backup header is damaged: {}", e);
+            Ok(Some(primary))
+        }
+        (Err(e), Ok(backup)) => {
+            warn!("GPT primary header is damaged, using the backup: {}", e);
+            Ok(Some(backup))
+        }
+        // no signature in either place: just not a GPT disk
+        (Err(primary), Err(_)) if block_has_no_signature(disk, 1) => {
+            debug!("No GPT: {}", primary);
+            Ok(None)
+        }
+        (Err(e), Err(_)) => Err(e),
+    }
+}
+
+fn block_has_no_signature(disk: &Disk, lba: u64) -> bool {
+    disk.read(lba, 1)
+        .is_ok_and(|block| &block[0..8] != GPT_SIGNATURE)
+}
+
+fn parse_entries(disk: Handle, header: &GptHeader, entries: &[u8]) -> vec::Vec<GptPartition> {
+    entries
+        .chunks_exact(header.entry_size)
+        .filter(|entry| entry[0..16].iter().any(|byte| *byte != 0))
+        .map(|entry| GptPartition {
+            disk,
+            type_guid: guid_at(entry, 0),
+            unique_guid: guid_at(entry, 16),
+            first_lba: le64(entry, 32),
+            last_lba: le64(entry, 40),
+            attributes: le64(entry, 48),
+            name: char::decode_utf16(
+                entry[56..128]
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                    .take_while(|unit| *unit != 0),
+            )
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect(),
+        })
+        .collect()
+}
+
+// every used partition entry on one disk
+pub fn read_partitions(handle: Handle) -> BootResult<vec::Vec<GptPartition>> {
+    let Some(disk) = open_disk(handle)? else {
+        return Ok(vec::Vec::new());
+    };
+
+    Ok(read_table(&disk)?
+        .map(|(header, entries)| parse_entries(handle, &header, &entries))
+        .unwrap_or_default())
+}
+
+// A9N system partitions on all disks, best candidate first: bootable ones, then by priority
+pub fn find_a9n_partitions() -> BootResult<vec::Vec<GptPartition>> {
+    let mut partitions = vec::Vec::new();
+    for handle in boot::find_handles::<BlockIO>().context("failed to enumerate block devices")? {
+        match read_partitions(handle) {
+            Ok(found) => partitions.extend(
+                found
+                    .into_iter()
+                    .filter(|partition| partition.type_guid == A9N_SYSTEM_PARTITION_TYPE),
+            ),
+            Err(e) => warn!("Skipping a disk with an unreadable GPT: {}", e),
+        }
+    }
+
+    for partition in &partitions {
+        debug!(
+            "A9N system partition {} '{}': LBA {}..={}, bootable {}, priority {}",
+            partition.unique_guid,
+            partition.name,
+            partition.first_lba,
+            partition.last_lba,
+            partition.is_bootable(),
+            partition.priority()
+        );
+    }
+
+    // stable, so disk order breaks ties
+    partitions.sort_by_key(|partition| {
+        core::cmp::Reverse((partition.is_bootable(), partition.priority()))
+    });
+    Ok(partitions)
+}
@@ -1,8 +1,12 @@
+use crate::config::{self, AddressPolicy};
 use crate::{debug, error, info, warn};
 
 use core::ptr::{copy_nonoverlapping, write_bytes};
 
-use crate::loader::elf;
+extern crate alloc;
+use alloc::vec;
+
+use crate::loader::{elf, track_allocation};
 use crate::util::*;
 use uefi::boot::{self, MemoryType};
 use xmas_elf::{
@@ -22,39 +26,299 @@ pub struct InitImageInfo {
     pub init_ipc_buffer_virtual_address: usize,
 }
 
+pub const KERNEL_SEGMENT_MAX: usize = 16;
+pub const KERNEL_BUILD_ID_MAX: usize = 64;
+
+// GNU build-id note (`ld --build-id`)
+const GNU_NOTE_NAME: &str = "GNU";
+const GNU_NOTE_BUILD_ID: u32 = 3;
+
+// one PT_LOAD of the kernel where it was loaded; flags are p_flags (PF_X 1, PF_W 2, PF_R 4)
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct KernelSegmentInfo {
+    pub physical_address: usize,
+    pub virtual_address: usize,
+    pub memory_size: usize,
+    pub flags: usize,
+}
+
+// physical_start/physical_end span every page allocated for the segments; build_id is the
+// first build_id_size bytes of the GNU build-id note (0 without one)
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct KernelImageInfo {
+    pub physical_start: usize,
+    pub physical_end: usize,
+    pub entry_point_physical_address: usize,
+    pub entry_point_virtual_address: usize,
+    pub segment_count: usize,
+    pub segments: [KernelSegmentInfo; KERNEL_SEGMENT_MAX],
+    pub build_id_size: usize,
+    pub build_id: [u8; KERNEL_BUILD_ID_MAX],
+}
+
+impl KernelImageInfo {
+    pub const EMPTY: Self = KernelImageInfo {
+        physical_start: 0,
+        physical_end: 0,
+        entry_point_physical_address: 0,
+        entry_point_virtual_address: 0,
+        segment_count: 0,
+        segments: [KernelSegmentInfo {
+            physical_address: 0,
+            virtual_address: 0,
+            memory_size: 0,
+            flags: 0,
+        }; KERNEL_SEGMENT_MAX],
+        build_id_size: 0,
+        build_id: [0; KERNEL_BUILD_ID_MAX],
+    };
+
+    // page ranges the kernel occupies, for the memory map
+    pub fn page_ranges(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.segments[..self.segment_count]
+            .iter()
+            .map(|segment| {
+                (
+                    segment.physical_address & !(EFI_PAGE_SIZE - 1),
+                    align_up(
+                        segment.physical_address + segment.memory_size,
+                        EFI_PAGE_SIZE,
+                    ),
+                )
+            })
+            .filter(|(start, end)| start != end)
+    }
+}
+
+// `A9N` note of type 1 in the kernel: u32 policy (0 paddr, 1 mask, 2 vma), u32 reserved,
+// u64 KERNEL_VMA offset for vma
+const A9N_NOTE_NAME: &str = "A9N";
+const A9N_NOTE_KERNEL_ADDRESS: u32 = 1;
+
+// config wins over the kernel's own note; neither means Mask
+fn kernel_address_policy(kernel_elf: &ElfFile) -> AddressPolicy {
+    if let Some(policy) = config::current().kernel_address {
+        info!("Kernel address policy: {} (config)", policy);
+        return policy;
+    }
+
+    let note = elf::find_note(kernel_elf, A9N_NOTE_NAME, A9N_NOTE_KERNEL_ADDRESS);
+    let policy = note.and_then(|description| {
+        let description: &[u8; 16] = description.try_into().ok()?;
+        let offset = u64::from_le_bytes(description[8..16].try_into().unwrap()) as usize;
+        match u32::from_le_bytes(description[0..4].try_into().unwrap()) {
+            0 => Some(AddressPolicy::Physical),
+            1 => Some(AddressPolicy::Mask),
+            2 => Some(AddressPolicy::VirtualOffset(offset)),
+            _ => None,
+        }
+    });
+
+    match (note, policy) {
+        (_, Some(policy)) => {
+            info!("Kernel address policy: {} (ELF note)", policy);
+            policy
+        }
+        (Some(_), None) => {
+            warn!("Ignoring malformed A9N address note, using mask");
+            AddressPolicy::Mask
+        }
+        (None, None) => {
+            info!("Kernel address policy: mask (default)");
+            AddressPolicy::Mask
+        }
+    }
+}
+
+// physical address `address` (a virtual address inside `program_header`) is loaded to
+fn translate(
+    policy: AddressPolicy,
+    program_header: &ProgramHeader,
+    address: usize,
+) -> Option<usize> {
+    let virtual_start = program_header.virtual_addr() as usize;
+    let load_start = match policy {
+        AddressPolicy::Physical => program_header.physical_addr() as usize,
+        AddressPolicy::Mask => (program_header.physical_addr() as usize) & !HIGHER_HALF_MASK,
+        AddressPolicy::VirtualOffset(offset) => virtual_start.checked_sub(offset)?,
+    };
+    load_start.checked_add(address.checked_sub(virtual_start)?)
+}
+
+// where each PT_LOAD goes, computed once so allocation, copying and the entry point agree
+#[derive(Debug, Clone, Copy)]
+struct KernelSegment<'a> {
+    program_header: ProgramHeader<'a>,
+    load_address: usize,
+}
+
+fn kernel_segments<'a>(
+    kernel_elf: &ElfFile<'a>,
+    policy: AddressPolicy,
+) -> BootResult<vec::Vec<KernelSegment<'a>>> {
+    kernel_elf
+        .program_iter()
+        .enumerate()
+        .filter(|(_, program_header)| filter_program_header_load(program_header))
+        .map(|(index, program_header)| {
+            let virtual_address = program_header.virtual_addr() as usize;
+            translate(policy, &program_header, virtual_address)
+                .filter(|load| {
+                    load.checked_add(program_header.mem_size() as usize)
+                        .is_some()
+                })
+                .map(|load_address| KernelSegment {
+                    program_header,
+                    load_address,
+                })
+                .ok_or(BootError::ElfInvalid(ElfViolation::Untranslatable {
+                    index,
+                    address: virtual_address,
+                }))
+        })
+        .collect()
+}
+
 pub fn load_kernel_at_physical_address(
     kernel_elf: &ElfFile,
     kernel_bytes: &[u8],
-) -> BootResult<usize> {
+) -> BootResult<KernelImageInfo> {
     info!("Loading kernel ...");
-    kernel_elf
-        .program_iter()
-        .filter(filter_program_header_load)
-        .try_for_each(|program_header| allocate_segment_at_exact_physical_address(&program_header))
+    let policy = kernel_address_policy(kernel_elf);
+    let segments = kernel_segments(kernel_elf, policy)?;
+    if segments.len() > KERNEL_SEGMENT_MAX {
+        return Err(ElfViolation::TooManySegments {
+            count: segments.len(),
+            max: KERNEL_SEGMENT_MAX,
+        }
+        .into());
+    }
+
+    check_segment_overlap(&segments)
         .and_then(|_| {
-            kernel_elf
-                .program_iter()
-                .filter(filter_program_header_load)
-                .try_for_each(|program_header| {
-                    copy_segment_to_physical_address(&program_header, kernel_bytes, 0)
-                })
+            segments
+                .iter()
+                .try_for_each(allocate_segment_at_exact_physical_address)
         })
-        .map(|_| {
+        .and_then(|_| {
+            segments
+                .iter()
+                .try_for_each(|segment| copy_segment_to_physical_address(segment, kernel_bytes))
+        })
+        .and_then(|_| {
+            // parse_elf made sure it is inside an executable segment
             let entry_point = kernel_elf.header.pt2.entry_point() as usize;
-            info!("Kernel entry point: 0x{:016x}", entry_point);
-            entry_point
+            segments
+                .iter()
+                .find_map(|segment| {
+                    let start = segment.program_header.virtual_addr() as usize;
+                    let end = start + segment.program_header.mem_size() as usize;
+                    (start..end)
+                        .contains(&entry_point)
+                        .then(|| translate(policy, &segment.program_header, entry_point))
+                        .flatten()
+                })
+                .ok_or(BootError::ElfInvalid(
+                    ElfViolation::EntryPointNotExecutable { entry: entry_point },
+                ))
+                .inspect(|physical_entry_point| {
+                    info!(
+                        "Kernel entry point: 0x{:016x} (linked at 0x{:016x})",
+                        physical_entry_point, entry_point
+                    )
+                })
+                .map(|physical_entry_point| {
+                    make_kernel_image_info(kernel_elf, &segments, physical_entry_point)
+                })
         })
 }
 
+fn make_kernel_image_info(
+    kernel_elf: &ElfFile,
+    segments: &[KernelSegment],
+    entry_point_physical_address: usize,
+) -> KernelImageInfo {
+    let mut kernel_image_info = KernelImageInfo {
+        entry_point_physical_address,
+        entry_point_virtual_address: kernel_elf.header.pt2.entry_point() as usize,
+        segment_count: segments.len(),
+        ..KernelImageInfo::EMPTY
+    };
+
+    for (info, segment) in kernel_image_info.segments.iter_mut().zip(segments) {
+        *info = KernelSegmentInfo {
+            physical_address: segment.load_address,
+            virtual_address: segment.program_header.virtual_addr() as usize,
+            memory_size: segment.program_header.mem_size() as usize,
+            flags: segment.program_header.flags().0 as usize,
+        };
+    }
+
+    let (start, end) = kernel_image_info
+        .page_ranges()
+        .fold((usize::MAX, 0), |(start, end), range| {
+            (start.min(range.0), end.max(range.1))
+        });
+    if start < end {
+        kernel_image_info.physical_start = start;
+        kernel_image_info.physical_end = end;
+    }
+
+    match elf::find_note(kernel_elf, GNU_NOTE_NAME, GNU_NOTE_BUILD_ID) {
+        Some(build_id) if build_id.len() <= KERNEL_BUILD_ID_MAX => {
+            kernel_image_info.build_id[..build_id.len()].copy_from_slice(build_id);
+            kernel_image_info.build_id_size = build_id.len();
+        }
+        Some(build_id) => warn!("Ignoring a {} byte kernel build-id", build_id.len()),
+        None => debug!("The kernel has no build-id note"),
+    }
+
+    kernel_image_info
+}
+
+// page ranges of the PT_LOAD segments as they are allocated below
+fn segment_page_range(segment: &KernelSegment) -> (usize, usize) {
+    let start = segment.load_address & !(EFI_PAGE_SIZE - 1);
+    let end = align_up(
+        segment.load_address + segment.program_header.mem_size() as usize,
+        EFI_PAGE_SIZE,
+    );
+    (start, end)
+}
+
+// otherwise the second allocation fails with a bare NOT_FOUND from the firmware
+fn check_segment_overlap(segments: &[KernelSegment]) -> BootResult<()> {
+    let ranges = segments
+        .iter()
+        .map(segment_page_range)
+        .filter(|(start, end)| start != end)
+        .collect::<vec::Vec<_>>();
+
+    for (index, first) in ranges.iter().enumerate() {
+        if let Some(second) = ranges[index + 1..]
+            .iter()
+            .find(|second| first.0 < second.1 && second.0 < first.1)
+        {
+            return Err(BootError::SegmentOverlap {
+                first: *first,
+                second: *second,
+            });
+        }
+    }
+
+    Ok(())
+}
+
 #[inline]
 fn filter_program_header_load(program_header: &ProgramHeader) -> bool {
     program_header.get_type() == Ok(ProgramHeaderType::Load)
 }
 
-fn allocate_segment_at_exact_physical_address(program_header: &ProgramHeader) -> BootResult<()> {
-    let physical_address = (program_header.physical_addr() as usize) & !HIGHER_HALF_MASK;
-    let memory_size = program_header.mem_size() as usize;
-    let pages = bytes_to_pages(memory_size);
+fn allocate_segment_at_exact_physical_address(segment: &KernelSegment) -> BootResult<()> {
+    let (physical_address, end) = segment_page_range(segment);
+    let pages = (end - physical_address) / EFI_PAGE_SIZE;
 
     if pages == 0 {
         // warn!("Program header with zero memory size: {:?}", program_header);
@@ -66,45 +330,48 @@ fn allocate_segment_at_exact_physical_address(program_header: &ProgramHeader) ->
         MemoryType::RESERVED,
         pages,
     )
-    .map_err(|e| e.status())
-    .map_err(crate::util::uefi_error)
+    .map_err(|e| BootError::AllocationFailed {
+        address: Some(physical_address),
+        pages,
+        memory_type: MemoryType::RESERVED,
+        status: e.status(),
+    })
     .map(|_| {
+        track_allocation(physical_address, pages);
         debug!(
             "Alloc segment at [0x{:016x}, 0x{:016x}] with {} pages",
-            physical_address,
-            physical_address + memory_size,
-            pages
+            physical_address, end, pages
         );
     })
 }
 
-fn copy_segment_to_physical_address(
-    program_header: &ProgramHeader,
-    image: &[u8],
-    physical_offset: usize,
-) -> BootResult<()> {
+fn copy_segment_to_physical_address(segment: &KernelSegment, image: &[u8]) -> BootResult<()> {
+    let program_header = &segment.program_header;
     debug!("Copying segment: {:?}", program_header);
     debug!(
         "  file size: 0x{:x}, memory size: 0x{:x}",
         program_header.file_size(),
         program_header.mem_size()
     );
-    if !filter_program_header_load(program_header) {
-        return Ok(());
-    }
 
     let file_size = program_header.file_size() as usize;
     let memory_size = program_header.mem_size() as usize;
     let file_offset = program_header.offset() as usize;
 
-    let physical_address = program_header.physical_addr() as usize + physical_offset;
+    let physical_address = segment.load_address;
     if file_size == 0 {
         debug!("  Skipping segment with zero file size");
-        // physical_address &= !HIGHER_HALF_MASK; // Ensure physical address is in lower half
     }
 
     if file_size > 0 {
-        let source = &image[file_offset..file_offset + file_size];
+        // parse_elf already checked this, but a bad slice here would panic instead of failing
+        let source = image
+            .get(file_offset..file_offset.saturating_add(file_size))
+            .ok_or(BootError::SegmentOutOfBounds {
+                address: file_offset,
+                size: file_size,
+                limit: image.len(),
+            })?;
         unsafe {
             copy_nonoverlapping(source.as_ptr(), physical_address as *mut u8, file_size);
         }
@@ -124,7 +391,7 @@ fn copy_segment_to_physical_address(
 pub fn load_init_at_anywhere(init_elf: &ElfFile, init_bytes: &[u8]) -> BootResult<InitImageInfo> {
     info!("Loading init ...");
 
-    let (span_start, span_end) = calculate_load_span_physical_address(init_elf);
+    let (span_start, span_end) = calculate_load_span_physical_address(init_elf)?;
     let total_bytes = span_end - span_start;
     let total_pages = bytes_to_pages_rounded(total_bytes);
 
@@ -144,6 +411,7 @@ pub fn load_init_at_anywhere(init_elf: &ElfFile, init_bytes: &[u8]) -> BootResul
     .map(|address| {
         base = address.as_ptr().addr();
         load_bias = base - span_start;
+        track_allocation(base, total_pages);
 
         debug!(
             "Allocated pages for init at physical address: 0x{:016x}, load bias: 0x{:x}",
@@ -152,7 +420,12 @@ pub fn load_init_at_anywhere(init_elf: &ElfFile, init_bytes: &[u8]) -> BootResul
     })
     .map_err(|e| {
         error!("Failed to allocate pages for init: {}", e);
-        e
+        BootError::AllocationFailed {
+            address: None,
+            pages: total_pages,
+            memory_type: MemoryType::RESERVED,
+            status: e.status(),
+        }
     })?;
 
     // configure variables for init image info
@@ -205,11 +478,11 @@ pub fn load_init_at_anywhere(init_elf: &ElfFile, init_bytes: &[u8]) -> BootResul
     })
 }
 
-fn calculate_load_span_physical_address(elf: &ElfFile) -> (usize, usize) {
+fn calculate_load_span_physical_address(elf: &ElfFile) -> BootResult<(usize, usize)> {
     let start = 0usize;
     let mut end = 0usize;
 
-    for program_header in elf.program_iter() {
+    for (index, program_header) in elf.program_iter().enumerate() {
         if !filter_program_header_load(&program_header) {
             continue;
         }
@@ -223,14 +496,17 @@ fn calculate_load_span_physical_address(elf: &ElfFile) -> (usize, usize) {
 
         let physical_end = physical_start
             .checked_add(memory_size)
-            .expect("ELF segment physical range overflow");
+            .filter(|end| end.checked_add(EFI_PAGE_SIZE).is_some())
+            .ok_or(BootError::ElfInvalid(ElfViolation::AddressOverflow {
+                index,
+            }))?;
 
         if physical_end > end {
             end = physical_end;
         }
     }
 
-    (start, align_up(end, EFI_PAGE_SIZE))
+    Ok((start, align_up(end, EFI_PAGE_SIZE)))
 }
 
 fn copy_segment_to_physical_address_checked(
@@ -254,13 +530,21 @@ fn copy_segment_to_physical_address_checked(
             "Segment paddr is below span_start: paddr=0x{:x}, span_start=0x{:x}",
             paddr, span_start
         );
-        return Err(uefi_error(uefi::Status::LOAD_ERROR));
+        return Err(BootError::SegmentOutOfBounds {
+            address: paddr,
+            size: memory_size,
+            limit: span_start,
+        });
     }
 
     let segment_offset = paddr - span_start;
 
     if segment_offset.checked_add(memory_size).is_none() {
-        return Err(uefi_error(uefi::Status::LOAD_ERROR));
+        return Err(BootError::SegmentOutOfBounds {
+            address: paddr,
+            size: memory_size,
+            limit: span_start + allocated_bytes,
+        });
     }
 
     let segment_end = segment_offset + memory_size;
@@ -270,7 +554,11 @@ fn copy_segment_to_physical_address_checked(
             "Segment exceeds allocated bytes: segment_end=0x{:x} > allocated=0x{:x} (paddr=0x{:x}, memsz=0x{:x})",
             segment_end, allocated_bytes, paddr, memory_size
         );
-        return Err(uefi_error(uefi::Status::LOAD_ERROR));
+        return Err(BootError::SegmentOutOfBounds {
+            address: paddr,
+            size: memory_size,
+            limit: span_start + allocated_bytes,
+        });
     }
 
     let dest = load_bias + paddr; // == allocation_base + segment_offset
@@ -283,7 +571,11 @@ fn copy_segment_to_physical_address_checked(
                 file_size,
                 image.len()
             );
-            return Err(uefi_error(uefi::Status::LOAD_ERROR));
+            return Err(BootError::SegmentOutOfBounds {
+                address: file_offset,
+                size: file_size,
+                limit: image.len(),
+            });
         }
 
         let source = &image[file_offset..file_offset + file_size];
@@ -310,6 +602,7 @@ pub fn reserve_ap_trampoline() -> BootResult<()> {
     );
 
     let try_types = [MemoryType::UNUSABLE, MemoryType::RESERVED];
+    let mut last_status = uefi::Status::OUT_OF_RESOURCES;
     for try_type in try_types {
         let result = boot::allocate_pages(
             boot::AllocateType::Address(AP_TRAMPOLINE_BASE as u64),
@@ -319,18 +612,24 @@ pub fn reserve_ap_trampoline() -> BootResult<()> {
 
         match result {
             Ok(_) => {
+                track_allocation(AP_TRAMPOLINE_BASE, 1);
                 info!("Reserved AP trampoline at 0x{:016x}", AP_TRAMPOLINE_BASE);
                 return Ok(());
             }
-            Err(_) => {
+            Err(e) => {
                 warn!(
                     "Failed to reserve AP trampoline at 0x{:016x}: {}",
-                    AP_TRAMPOLINE_BASE,
-                    result.unwrap_err()
+                    AP_TRAMPOLINE_BASE, e
                 );
+                last_status = e.status();
             }
         }
     }
 
-    Err(uefi_error(uefi::Status::OUT_OF_RESOURCES))
+    Err(BootError::AllocationFailed {
+        address: Some(AP_TRAMPOLINE_BASE),
+        pages: 1,
+        memory_type: MemoryType::RESERVED,
+        status: last_status,
+    })
 }
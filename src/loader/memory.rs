@@ -1,8 +1,12 @@
 extern crate alloc;
 
+use alloc::vec;
+use core::ptr::NonNull;
+
 use uefi::boot::MemoryType;
 use uefi::mem::memory_map::MemoryMap;
 
+use crate::loader::BOOT_INFO;
 use crate::util::*;
 
 #[repr(C)]
@@ -11,6 +15,8 @@ pub enum MemoryMapType {
     Free,
     Device,
     Reserved,
+    // pages holding the kernel's PT_LOAD segments (BootInfo::kernel_image_info)
+    KernelImage,
 }
 
 #[repr(C)]
@@ -31,113 +37,97 @@ pub struct MemoryInfo {
 
 // make memory info from uefi memory map
 
-static mut MEMORY_MAP_BUFFER: [MemoryMapEntry; 256] = [MemoryMapEntry {
+const MEMORY_MAP_MAX: usize = 256;
+
+static mut MEMORY_MAP_BUFFER: [MemoryMapEntry; MEMORY_MAP_MAX] = [MemoryMapEntry {
     physical_address_start: 0,
     page_count: 0,
     memory_type: MemoryMapType::Reserved,
-}; 256];
+}; MEMORY_MAP_MAX];
 
 pub fn make_memory_info() -> BootResult<MemoryInfo> {
     let mut memory_map_count: u16 = 0;
 
-    uefi::boot::memory_map(uefi::mem::memory_map::MemoryType::LOADER_DATA).and_then(|buffer| {
-        buffer
-            .entries()
-            .enumerate()
-            .try_for_each(|(i, entry)| unsafe {
-                // add or merge entry logic
-                let new_entry = MemoryMapEntry {
-                    physical_address_start: entry.phys_start as usize,
-                    page_count: entry.page_count as usize,
-                    memory_type: match entry.ty {
-                        MemoryType::CONVENTIONAL
-                        | MemoryType::ACPI_RECLAIM
-                        | MemoryType::PERSISTENT_MEMORY => MemoryMapType::Free,
-                        MemoryType::RESERVED
-                        | MemoryType::BOOT_SERVICES_CODE
-                        | MemoryType::BOOT_SERVICES_DATA
-                        | MemoryType::RUNTIME_SERVICES_CODE
-                        | MemoryType::RUNTIME_SERVICES_DATA
-                        | MemoryType::UNUSABLE
-                        | MemoryType::ACPI_NON_VOLATILE
-                        | MemoryType::PAL_CODE => MemoryMapType::Reserved,
-                        MemoryType::LOADER_CODE
-                        | MemoryType::LOADER_DATA
-                        | MemoryType::MMIO
-                        | MemoryType::MMIO_PORT_SPACE
-                        | _ => MemoryMapType::Device,
-                    },
-                };
-                let last_entry = if memory_map_count > 0 {
-                    &mut MEMORY_MAP_BUFFER[(memory_map_count - 1) as usize]
-                } else {
-                    core::ptr::null_mut()
-                };
-                if !last_entry.is_null()
-                    && (*last_entry).memory_type == new_entry.memory_type
-                    && ((*last_entry).physical_address_start
-                        + ((*last_entry).page_count * EFI_PAGE_SIZE)
-                        == new_entry.physical_address_start)
-                {
-                    (*last_entry).page_count += new_entry.page_count;
-                } else {
-                    MEMORY_MAP_BUFFER[memory_map_count as usize] = new_entry;
-                    memory_map_count += 1;
-                }
-
-                // making "gap" entry logic (w (1 << 46) max address)
-                if i + 1 < buffer.entries().len() {
-                    let next_entry = buffer
-                        .entries()
-                        .nth(i + 1)
-                        .ok_or(uefi_error(uefi::Status::INVALID_PARAMETER))?;
-                    let last_processed_addr =
-                        entry.phys_start as usize + (entry.page_count as usize * EFI_PAGE_SIZE);
-                    if next_entry.phys_start as usize > last_processed_addr {
-                        let gap_entry = MemoryMapEntry {
-                            physical_address_start: last_processed_addr,
-                            page_count: (next_entry.phys_start as usize - last_processed_addr)
-                                / EFI_PAGE_SIZE,
-                            memory_type: MemoryMapType::Device,
-                        };
-                        let last_entry = &mut MEMORY_MAP_BUFFER[(memory_map_count - 1) as usize];
-                        if last_entry.memory_type == gap_entry.memory_type
-                            && (last_entry.physical_address_start
-                                + (last_entry.page_count * EFI_PAGE_SIZE)
-                                == gap_entry.physical_address_start)
-                        {
-                            last_entry.page_count += gap_entry.page_count;
-                        } else {
-                            MEMORY_MAP_BUFFER[memory_map_count as usize] = gap_entry;
-                            memory_map_count += 1;
-                        }
+    uefi::boot::memory_map(uefi::mem::memory_map::MemoryType::LOADER_DATA)
+        .and_then(|buffer| {
+            buffer
+                .entries()
+                .enumerate()
+                .try_for_each(|(i, entry)| {
+                    // add or merge entry logic
+                    let new_entry = MemoryMapEntry {
+                        physical_address_start: entry.phys_start as usize,
+                        page_count: entry.page_count as usize,
+                        memory_type: match entry.ty {
+                            MemoryType::CONVENTIONAL
+                            | MemoryType::ACPI_RECLAIM
+                            | MemoryType::PERSISTENT_MEMORY => MemoryMapType::Free,
+                            MemoryType::RESERVED
+                            | MemoryType::BOOT_SERVICES_CODE
+                            | MemoryType::BOOT_SERVICES_DATA
+                            | MemoryType::RUNTIME_SERVICES_CODE
+                            | MemoryType::RUNTIME_SERVICES_DATA
+                            | MemoryType::UNUSABLE
+                            | MemoryType::ACPI_NON_VOLATILE
+                            | MemoryType::PAL_CODE => MemoryMapType::Reserved,
+                            MemoryType::LOADER_CODE
+                            | MemoryType::LOADER_DATA
+                            | MemoryType::MMIO
+                            | MemoryType::MMIO_PORT_SPACE
+                            | _ => MemoryMapType::Device,
+                        },
+                    };
+                    // the kernel's own pages become KernelImage entries
+                    let end =
+                        new_entry.physical_address_start + new_entry.page_count * EFI_PAGE_SIZE;
+                    let mut start = new_entry.physical_address_start;
+                    while start < end {
+                        let (piece_end, memory_type) = split_kernel_image(start, end);
+                        push_entry(
+                            &mut memory_map_count,
+                            MemoryMapEntry {
+                                physical_address_start: start,
+                                page_count: (piece_end - start) / EFI_PAGE_SIZE,
+                                memory_type: memory_type.unwrap_or(new_entry.memory_type),
+                            },
+                        )?;
+                        start = piece_end;
                     }
-                } else {
-                    let max_address = (1usize) << 46;
-                    let last_processed_addr =
-                        entry.phys_start as usize + (entry.page_count as usize * EFI_PAGE_SIZE);
-                    if last_processed_addr < max_address {
-                        let final_gap_entry = MemoryMapEntry {
-                            physical_address_start: last_processed_addr,
-                            page_count: (max_address - last_processed_addr) / EFI_PAGE_SIZE,
-                            memory_type: MemoryMapType::Device,
-                        };
-                        let last_entry = &mut MEMORY_MAP_BUFFER[(memory_map_count - 1) as usize];
-                        if last_entry.memory_type == final_gap_entry.memory_type
-                            && (last_entry.physical_address_start
-                                + (last_entry.page_count * EFI_PAGE_SIZE)
-                                == final_gap_entry.physical_address_start)
-                        {
-                            last_entry.page_count += final_gap_entry.page_count;
-                        } else {
-                            MEMORY_MAP_BUFFER[memory_map_count as usize] = final_gap_entry;
-                            memory_map_count += 1;
+
+                    // making "gap" entry logic (w (1 << 46) max address)
+                    if i + 1 < buffer.entries().len() {
+                        let next_entry = buffer
+                            .entries()
+                            .nth(i + 1)
+                            .ok_or(uefi::Error::from(uefi::Status::INVALID_PARAMETER))?;
+                        let last_processed_addr =
+                            entry.phys_start as usize + (entry.page_count as usize * EFI_PAGE_SIZE);
+                        if next_entry.phys_start as usize > last_processed_addr {
+                            let gap_entry = MemoryMapEntry {
+                                physical_address_start: last_processed_addr,
+                                page_count: (next_entry.phys_start as usize - last_processed_addr)
+                                    / EFI_PAGE_SIZE,
+                                memory_type: MemoryMapType::Device,
+                            };
+                            push_entry(&mut memory_map_count, gap_entry)?;
+                        }
+                    } else {
+                        let max_address = (1usize) << 46;
+                        let last_processed_addr =
+                            entry.phys_start as usize + (entry.page_count as usize * EFI_PAGE_SIZE);
+                        if last_processed_addr < max_address {
+                            let final_gap_entry = MemoryMapEntry {
+                                physical_address_start: last_processed_addr,
+                                page_count: (max_address - last_processed_addr) / EFI_PAGE_SIZE,
+                                memory_type: MemoryMapType::Device,
+                            };
+                            push_entry(&mut memory_map_count, final_gap_entry)?;
                         }
                     }
-                }
-                Ok(())
-            })
-    })?;
+                    Ok(())
+                })
+        })
+        .context("failed to build the memory map")?;
 
     Ok(MemoryInfo {
         memory_size: 0, // maybe unused
@@ -146,3 +136,63 @@ pub fn make_memory_info() -> BootResult<MemoryInfo> {
         memory_map: unsafe { MEMORY_MAP_BUFFER.as_mut_ptr() },
     })
 }
+
+// end of the piece of [start, end) starting at `start` that is entirely inside or outside
+// the kernel image, and KernelImage if inside
+fn split_kernel_image(start: usize, end: usize) -> (usize, Option<MemoryMapType>) {
+    #[allow(static_mut_refs)]
+    let kernel_image_info = unsafe { &BOOT_INFO.kernel_image_info };
+
+    kernel_image_info.page_ranges().fold(
+        (end, None),
+        |(piece_end, memory_type), (kernel_start, kernel_end)| {
+            if kernel_start <= start && start < kernel_end {
+                (piece_end.min(kernel_end), Some(MemoryMapType::KernelImage))
+            } else if start < kernel_start && kernel_start < piece_end {
+                (kernel_start, memory_type)
+            } else {
+                (piece_end, memory_type)
+            }
+        },
+    )
+}
+
+// appended, or merged into the previous entry if it continues it
+fn push_entry(memory_map_count: &mut u16, entry: MemoryMapEntry) -> uefi::Result<()> {
+    #[allow(static_mut_refs)]
+    let buffer = unsafe { &mut MEMORY_MAP_BUFFER };
+    if let Some(last_entry) = buffer[..*memory_map_count as usize].last_mut()
+        && last_entry.memory_type == entry.memory_type
+        && last_entry.physical_address_start + last_entry.page_count * EFI_PAGE_SIZE
+            == entry.physical_address_start
+    {
+        last_entry.page_count += entry.page_count;
+    } else {
+        *buffer
+            .get_mut(*memory_map_count as usize)
+            .ok_or(uefi::Error::from(uefi::Status::BUFFER_TOO_SMALL))? = entry;
+        *memory_map_count += 1;
+    }
+    Ok(())
+}
+
+// pages claimed for the kernel and init, so a retried load starts from a clean slate
+static mut LOADER_ALLOCATIONS: vec::Vec<(usize, usize)> = vec::Vec::new();
+
+pub fn track_allocation(address: usize, pages: usize) {
+    #[allow(static_mut_refs)]
+    unsafe {
+        LOADER_ALLOCATIONS.push((address, pages))
+    };
+}
+
+pub fn release_allocations() {
+    #[allow(static_mut_refs)]
+    let allocations = unsafe { core::mem::take(&mut LOADER_ALLOCATIONS) };
+
+    for (address, pages) in allocations {
+        if let Some(pointer) = NonNull::new(address as *mut u8) {
+            let _ = unsafe { uefi::boot::free_pages(pointer, pages) };
+        }
+    }
+}
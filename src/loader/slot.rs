@@ -0,0 +1,235 @@
+use crate::loader::{BOOT_INFO, BootSlotInfo};
+use crate::{config, info, warn};
+
+extern crate alloc;
+use alloc::format;
+use alloc::string::String;
+
+use uefi::runtime::{self, VariableAttributes, VariableVendor};
+use uefi::{CStr16, cstr16, guid};
+
+// owner of the loader's NVRAM variables
+pub const A9NLOADER_VENDOR: VariableVendor =
+    VariableVendor(guid!("5a9e4c1d-7b3f-4e8a-9c2d-a9b0c5e20001"));
+
+// [good slot, trial slot (SLOT_NONE if none), tries left]
+const SLOT_STATE: &CStr16 = cstr16!("A9NSlotState");
+// 1 byte slot index, written by the OS to request a trial boot of that slot
+const SLOT_TRIAL: &CStr16 = cstr16!("A9NSlotTrial");
+// written (any content) by the kernel once a trial boot came up fine
+const SLOT_SUCCESS: &CStr16 = cstr16!("A9NBootSuccess");
+
+const SLOT_NONE: u8 = 0xff;
+const SLOT_NAMES: [&str; 2] = ["a", "b"];
+
+// BootSlotInfo::flags
+pub const BOOT_SLOT_TRIAL: usize = 1 << 0;
+pub const BOOT_SLOT_ROLLED_BACK: usize = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SlotState {
+    good: u8,
+    trial: u8,
+    tries_left: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Selection {
+    slot: u8,
+    flags: usize,
+}
+
+static mut SELECTION: Option<Selection> = None;
+
+fn read_variable(name: &CStr16, buffer: &mut [u8]) -> Option<usize> {
+    runtime::get_variable(name, &A9NLOADER_VENDOR, buffer)
+        .ok()
+        .map(|(data, _)| data.len())
+}
+
+// BUFFER_TOO_SMALL still means it's there
+fn variable_exists(name: &CStr16) -> bool {
+    match runtime::get_variable(name, &A9NLOADER_VENDOR, &mut [0u8; 1]) {
+        Ok(_) => true,
+        Err(e) => e.data().is_some(),
+    }
+}
+
+// false if the firmware refused the write
+fn write_variable(name: &CStr16, data: &[u8]) -> bool {
+    // RUNTIME_ACCESS so the kernel can still read the slot state after exit_boot_services;
+    // A9NBootSuccess is created by the kernel itself, never by the loader
+    let attributes = VariableAttributes::NON_VOLATILE
+        | VariableAttributes::BOOTSERVICE_ACCESS
+        | VariableAttributes::RUNTIME_ACCESS;
+
+    runtime::set_variable(name, &A9NLOADER_VENDOR, attributes, data)
+        .inspect_err(|e| warn!("Failed to write {}: {}", name, e))
+        .is_ok()
+}
+
+fn delete_variable(name: &CStr16) {
+    let _ = runtime::delete_variable(name, &A9NLOADER_VENDOR);
+}
+
+fn valid_slot(slot: u8) -> bool {
+    (slot as usize) < SLOT_NAMES.len()
+}
+
+fn read_state() -> SlotState {
+    let mut buffer = [0u8; 3];
+    match read_variable(SLOT_STATE, &mut buffer) {
+        Some(3) if valid_slot(buffer[0]) => SlotState {
+            good: buffer[0],
+            trial: if valid_slot(buffer[1]) {
+                buffer[1]
+            } else {
+                SLOT_NONE
+            },
+            tries_left: buffer[2],
+        },
+        _ => SlotState {
+            good: 0,
+            trial: SLOT_NONE,
+            tries_left: 0,
+        },
+    }
+}
+
+fn write_state(state: &SlotState) -> bool {
+    write_variable(SLOT_STATE, &[state.good, state.trial, state.tries_left])
+}
+
+// decide the slot for this boot and count the attempt; once per boot, not per retry
+pub fn select_slot() {
+    let config = config::current();
+    if !config.slots {
+        return;
+    }
+
+    let stored = read_state();
+    let mut state = stored;
+
+    // the last trial boot made it
+    if variable_exists(SLOT_SUCCESS) {
+        delete_variable(SLOT_SUCCESS);
+        if state.trial != SLOT_NONE {
+            info!(
+                "Slot {} booted successfully, it is now the good slot",
+                slot_name(state.trial)
+            );
+            state.good = state.trial;
+            state.trial = SLOT_NONE;
+        }
+    }
+
+    // a new trial requested from the OS
+    let mut buffer = [0u8; 1];
+    if read_variable(SLOT_TRIAL, &mut buffer).is_some() {
+        delete_variable(SLOT_TRIAL);
+        match buffer[0] {
+            slot if valid_slot(slot) && slot != state.good => {
+                info!(
+                    "Trying slot {} up to {} times",
+                    slot_name(slot),
+                    config.slot_tries
+                );
+                state.trial = slot;
+                state.tries_left = config.slot_tries;
+            }
+            slot => warn!("Ignoring trial request for slot {}", slot),
+        }
+    }
+
+    let mut selection = match state.trial {
+        SLOT_NONE => Selection {
+            slot: state.good,
+            flags: 0,
+        },
+        trial if state.tries_left > 0 => {
+            state.tries_left -= 1;
+            Selection {
+                slot: trial,
+                flags: BOOT_SLOT_TRIAL,
+            }
+        }
+        trial => {
+            warn!(
+                "Slot {} never reported a successful boot, rolling back to slot {}",
+                slot_name(trial),
+                slot_name(state.good)
+            );
+            state.trial = SLOT_NONE;
+            Selection {
+                slot: state.good,
+                flags: BOOT_SLOT_ROLLED_BACK,
+            }
+        }
+    };
+
+    // NVRAM wears out, so only write when something changed
+    if state != stored && !write_state(&state) && selection.flags & BOOT_SLOT_TRIAL != 0 {
+        // an uncounted trial could be retried forever, treat it as out of tries
+        warn!(
+            "Couldn't count the boot of slot {}, rolling back to slot {}",
+            slot_name(selection.slot),
+            slot_name(state.good)
+        );
+        state.trial = SLOT_NONE;
+        write_state(&state);
+        selection = Selection {
+            slot: state.good,
+            flags: BOOT_SLOT_ROLLED_BACK,
+        };
+    }
+
+    set_selection(selection);
+}
+
+fn set_selection(selection: Selection) {
+    info!("Booting slot {}", slot_name(selection.slot));
+    unsafe {
+        SELECTION = Some(selection);
+        BOOT_INFO.boot_slot_info = BootSlotInfo {
+            slot: selection.slot as usize,
+            flags: selection.flags,
+        };
+    }
+}
+
+// a trial slot that doesn't even load goes straight back to the good one
+pub fn fall_back_to_good_slot() -> bool {
+    let Some(selection) = (unsafe { SELECTION }) else {
+        return false;
+    };
+    if selection.flags & BOOT_SLOT_TRIAL == 0 {
+        return false;
+    }
+
+    let mut state = read_state();
+    warn!(
+        "Slot {} failed to load, rolling back to slot {}",
+        slot_name(selection.slot),
+        slot_name(state.good)
+    );
+    state.trial = SLOT_NONE;
+    write_state(&state);
+
+    set_selection(Selection {
+        slot: state.good,
+        flags: BOOT_SLOT_ROLLED_BACK,
+    });
+    true
+}
+
+fn slot_name(slot: u8) -> &'static str {
+    SLOT_NAMES.get(slot as usize).copied().unwrap_or("?")
+}
+
+// `\kernel\<slot>\<file>` with slots enabled, `\kernel\<file>` otherwise
+pub fn slot_path(file: &str) -> String {
+    match unsafe { SELECTION } {
+        Some(selection) => format!(r"\kernel\{}\{}", slot_name(selection.slot), file),
+        None => format!(r"\kernel\{}", file),
+    }
+}
@@ -0,0 +1,118 @@
+use crate::info;
+use crate::loader::track_allocation;
+use crate::util::*;
+
+use core::ptr::copy_nonoverlapping;
+
+use uefi::boot::{self, MemoryType};
+use xmas_elf::ElfFile;
+use xmas_elf::sections::{SectionHeader, ShType};
+
+// section headers, .symtab and the string table it links to, copied as they are in the file
+// into one RESERVED allocation; all zero if the image has no symbol table or none was asked for
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolTableInfo {
+    pub section_headers: usize,
+    pub section_header_count: usize,
+    pub section_header_size: usize,
+    pub symbol_table: usize,
+    pub symbol_table_size: usize,
+    pub symbol_entry_size: usize,
+    pub string_table: usize,
+    pub string_table_size: usize,
+}
+
+impl SymbolTableInfo {
+    pub const EMPTY: Self = SymbolTableInfo {
+        section_headers: 0,
+        section_header_count: 0,
+        section_header_size: 0,
+        symbol_table: 0,
+        symbol_table_size: 0,
+        symbol_entry_size: 0,
+        string_table: 0,
+        string_table_size: 0,
+    };
+}
+
+// parse_elf checks the header tables but not what the sections point at
+fn section_bytes<'a>(elf: &ElfFile<'a>, section_header: &SectionHeader) -> Option<&'a [u8]> {
+    let offset = section_header.offset() as usize;
+    elf.input
+        .get(offset..offset.checked_add(section_header.size() as usize)?)
+}
+
+// the first .symtab with its string table
+fn find_symbol_table<'a>(elf: &ElfFile<'a>) -> Option<(SectionHeader<'a>, &'a [u8], &'a [u8])> {
+    elf.section_iter()
+        .filter(|section_header| section_header.get_type() == Ok(ShType::SymTab))
+        .find_map(|symbol_table| {
+            let string_table = elf.section_header(symbol_table.link() as u16).ok()?;
+            Some((
+                symbol_table,
+                section_bytes(elf, &symbol_table)?,
+                section_bytes(elf, &string_table)?,
+            ))
+        })
+}
+
+pub fn load_symbol_table(elf: &ElfFile, name: &str) -> BootResult<SymbolTableInfo> {
+    let Some((symbol_header, symbols, strings)) = find_symbol_table(elf) else {
+        info!("No symbol table in {} (stripped?)", name);
+        return Ok(SymbolTableInfo::EMPTY);
+    };
+
+    let header = &elf.header.pt2;
+    let section_header_offset = header.sh_offset() as usize;
+    let section_header_count = header.sh_count() as usize;
+    let section_header_size = header.sh_entry_size() as usize;
+    let section_headers = elf
+        .input
+        .get(section_header_offset..)
+        .and_then(|table| table.get(..section_header_count * section_header_size))
+        .ok_or(BootError::ElfParse {
+            reason: "section header table out of bounds",
+        })?;
+
+    // one allocation, each part 8 byte aligned
+    let symbols_offset = align_up(section_headers.len(), 8);
+    let strings_offset = align_up(symbols_offset + symbols.len(), 8);
+    let pages = bytes_to_pages_rounded(strings_offset + strings.len());
+    let address = boot::allocate_pages(boot::AllocateType::AnyPages, MemoryType::RESERVED, pages)
+        .map_err(|e| BootError::AllocationFailed {
+            address: None,
+            pages,
+            memory_type: MemoryType::RESERVED,
+            status: e.status(),
+        })?
+        .as_ptr()
+        .addr();
+    track_allocation(address, pages);
+
+    for (offset, bytes) in [
+        (0, section_headers),
+        (symbols_offset, symbols),
+        (strings_offset, strings),
+    ] {
+        unsafe { copy_nonoverlapping(bytes.as_ptr(), (address + offset) as *mut u8, bytes.len()) };
+    }
+
+    info!(
+        "Symbols of {}: {} bytes of symbols, {} bytes of strings at 0x{:016x}",
+        name,
+        symbols.len(),
+        strings.len(),
+        address
+    );
+    Ok(SymbolTableInfo {
+        section_headers: address,
+        section_header_count,
+        section_header_size,
+        symbol_table: address + symbols_offset,
+        symbol_table_size: symbols.len(),
+        symbol_entry_size: symbol_header.entry_size() as usize,
+        string_table: address + strings_offset,
+        string_table_size: strings.len(),
+    })
+}
@@ -0,0 +1,167 @@
+use crate::util::*;
+use crate::{info, warn};
+
+extern crate alloc;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+
+use uefi::boot::{self, MemoryType, OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol};
+use uefi::proto::network::IpAddress;
+use uefi::proto::network::pxe::{BaseCode, DhcpV4Packet};
+use uefi::{CStr8, Handle, Status};
+
+fn open_base_code(nic: Handle) -> BootResult<ScopedProtocol<BaseCode>> {
+    unsafe {
+        boot::open_protocol::<BaseCode>(
+            OpenProtocolParams {
+                handle: nic,
+                agent: boot::image_handle(),
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    }
+    .context("failed to open the PXE base code protocol")
+}
+
+pub fn format_ipv4(address: [u8; 4]) -> String {
+    format!(
+        "{}.{}.{}.{}",
+        address[0], address[1], address[2], address[3]
+    )
+}
+
+// `\kernel\kernel.elf` -> `kernel/kernel.elf`, relative to the TFTP root
+fn to_tftp_path(filepath: &str) -> vec::Vec<u8> {
+    let mut path = filepath.trim_start_matches(['\\', '/']).replace('\\', "/");
+    path.push('\0');
+    path.into_bytes()
+}
+
+// started and configured over DHCP, unless the firmware already did that (e.g. when the
+// loader itself was PXE booted)
+fn configure(base_code: &mut BaseCode) -> BootResult<()> {
+    match base_code.start(false) {
+        Ok(()) => {}
+        Err(e) if e.status() == Status::ALREADY_STARTED => {}
+        Err(e) => return Err(e).context("failed to start PXE"),
+    }
+
+    if !base_code.mode().dhcp_ack_received() {
+        info!("Requesting an address over DHCP ...");
+        base_code.dhcp(true).context("DHCP failed")?;
+    }
+    Ok(())
+}
+
+// next-server from a proxy DHCP offer, or the DHCP server's own answer
+fn boot_server(base_code: &BaseCode) -> Option<[u8; 4]> {
+    let mode = base_code.mode();
+    let proxy = mode
+        .proxy_offer_received()
+        .then(|| AsRef::<DhcpV4Packet>::as_ref(mode.proxy_offer()).bootp_si_addr);
+    let ack = AsRef::<DhcpV4Packet>::as_ref(mode.dhcp_ack()).bootp_si_addr;
+
+    proxy
+        .into_iter()
+        .chain([ack])
+        .find(|address| *address != [0; 4])
+}
+
+// first NIC that gets an address, and the server to read from: `server` or the one DHCP names
+pub fn find_tftp_server(server: Option<[u8; 4]>) -> BootResult<(Handle, [u8; 4])> {
+    let nics = boot::find_handles::<BaseCode>().context("no network interface with PXE support")?;
+
+    for nic in nics {
+        let result = open_base_code(nic).and_then(|mut base_code| {
+            configure(&mut base_code)?;
+            let station: [u8; 4] = base_code.mode().station_ip().0[..4].try_into().unwrap();
+            server
+                .or_else(|| boot_server(&base_code))
+                .map(|server| (station, server))
+                .ok_or(BootError::Uefi(Status::NOT_FOUND))
+                .context("DHCP didn't name a TFTP server")
+        });
+
+        match result {
+            Ok((station, server)) => {
+                info!(
+                    "Network up as {}, TFTP server {}",
+                    format_ipv4(station),
+                    format_ipv4(server)
+                );
+                return Ok((nic, server));
+            }
+            Err(e) => warn!("Skipping a network interface: {}", e),
+        }
+    }
+
+    Err(BootError::VolumeNotFound {
+        selector: "tftp".to_string(),
+    })
+}
+
+pub fn tftp_read_file(nic: Handle, server: [u8; 4], filepath: &str) -> BootResult<vec::Vec<u8>> {
+    info!(
+        "Reading file: {} (tftp://{})",
+        filepath,
+        format_ipv4(server)
+    );
+
+    let path = to_tftp_path(filepath);
+    let filename = CStr8::from_bytes_with_nul(&path).map_err(|_| BootError::FileRead {
+        path: filepath.to_string(),
+        status: Status::INVALID_PARAMETER,
+    })?;
+    let server = IpAddress::new_v4(server);
+    let mut base_code = open_base_code(nic)?;
+
+    let read_error = |e: uefi::Error| match e.status() {
+        // the server answered with an error packet, almost always "file not found"
+        Status::TFTP_ERROR => BootError::FileNotFound {
+            path: filepath.to_string(),
+        },
+        status => BootError::FileRead {
+            path: filepath.to_string(),
+            status,
+        },
+    };
+
+    let size = base_code
+        .tftp_get_file_size(&server, filename)
+        .map_err(read_error)? as usize;
+    info!("File size: {} bytes", size);
+
+    // the size comes from the server, don't let a bogus one abort the loader
+    let mut buffer = vec::Vec::new();
+    buffer
+        .try_reserve_exact(size)
+        .map_err(|_| BootError::AllocationFailed {
+            address: None,
+            pages: bytes_to_pages_rounded(size),
+            memory_type: MemoryType::LOADER_DATA,
+            status: Status::OUT_OF_RESOURCES,
+        })?;
+    buffer.resize(size, 0);
+
+    base_code
+        .tftp_read_file(&server, filename, Some(&mut buffer))
+        .map_err(read_error)
+        .map(|read| {
+            buffer.truncate(read as usize);
+            buffer
+        })
+}
+
+pub fn tftp_file_exists(nic: Handle, server: [u8; 4], filepath: &str) -> bool {
+    let path = to_tftp_path(filepath);
+    let Ok(filename) = CStr8::from_bytes_with_nul(&path) else {
+        return false;
+    };
+    open_base_code(nic).is_ok_and(|mut base_code| {
+        base_code
+            .tftp_get_file_size(&IpAddress::new_v4(server), filename)
+            .is_ok()
+    })
+}
@@ -0,0 +1,218 @@
+use crate::config::VolumeSelector;
+use crate::loader::{
+    BOOT_INFO, BootPartitionInfo, Ext2, file_exists, find_a9n_partitions, find_tftp_server,
+    read_entire_file_from, tftp_file_exists, tftp_read_file,
+};
+use crate::util::*;
+use crate::{debug, info};
+
+extern crate alloc;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+
+use uefi::boot::{self, OpenProtocolAttributes, OpenProtocolParams};
+use uefi::proto::device_path::media::PartitionSignature;
+use uefi::proto::device_path::{DevicePath, DevicePathNodeEnum};
+use uefi::proto::loaded_image::LoadedImage;
+use uefi::proto::media::disk::DiskIo;
+use uefi::proto::media::file::{File, FileSystemInfo};
+use uefi::proto::media::fs::SimpleFileSystem;
+use uefi::{Guid, Handle};
+
+// somewhere the kernel can be read from: through the firmware's file system driver (FAT),
+// through our own read-only ext2 driver for partitions the firmware can't read, or from a
+// TFTP server reached through a network interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Volume {
+    Firmware(Handle),
+    Ext2(Handle),
+    Tftp { nic: Handle, server: [u8; 4] },
+}
+
+impl Volume {
+    fn handle(&self) -> Handle {
+        match self {
+            Volume::Firmware(handle) | Volume::Ext2(handle) => *handle,
+            Volume::Tftp { nic, .. } => *nic,
+        }
+    }
+
+    pub fn read_entire_file(&self, filepath: &str) -> BootResult<vec::Vec<u8>> {
+        match self {
+            Volume::Firmware(handle) => read_entire_file_from(*handle, filepath),
+            Volume::Ext2(handle) => {
+                info!("Reading file: {} (ext2)", filepath);
+                open_ext2(*handle)?.read_file(filepath)
+            }
+            Volume::Tftp { nic, server } => tftp_read_file(*nic, *server, filepath),
+        }
+    }
+
+    fn exists(&self, filepath: &str) -> bool {
+        match self {
+            Volume::Firmware(handle) => file_exists(*handle, filepath),
+            Volume::Ext2(handle) => open_ext2(*handle).is_ok_and(|ext2| ext2.exists(filepath)),
+            Volume::Tftp { nic, server } => tftp_file_exists(*nic, *server, filepath),
+        }
+    }
+
+    fn label(&self) -> Option<String> {
+        match self {
+            Volume::Firmware(handle) => volume_label(*handle),
+            Volume::Ext2(handle) => open_ext2(*handle).ok().map(|ext2| ext2.label().to_string()),
+            Volume::Tftp { .. } => None,
+        }
+    }
+}
+
+fn open_ext2(handle: Handle) -> BootResult<Ext2> {
+    Ext2::open(handle)?.ok_or(BootError::FileSystem {
+        reason: "not an ext2 volume",
+    })
+}
+
+// the volume the loader itself was started from
+pub fn loader_volume() -> BootResult<Handle> {
+    boot::open_protocol_exclusive::<LoadedImage>(boot::image_handle())?
+        .device()
+        .ok_or(uefi_error(uefi::Status::NOT_FOUND))
+        .context("failed to find the loader volume")
+}
+
+// loader volume first, so `auto` prefers it when both have the kernel; then everything the
+// firmware can read, then ext2 partitions it can't
+fn volumes() -> BootResult<vec::Vec<Volume>> {
+    let loader = loader_volume()?;
+    let file_systems = boot::find_handles::<SimpleFileSystem>()
+        .context("failed to enumerate file system volumes")?;
+
+    let mut volumes = vec![Volume::Firmware(loader)];
+    volumes.extend(
+        file_systems
+            .iter()
+            .filter(|handle| **handle != loader)
+            .map(|handle| Volume::Firmware(*handle)),
+    );
+    volumes.extend(
+        boot::find_handles::<DiskIo>()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|handle| !file_systems.contains(handle))
+            .filter(|handle| matches!(Ext2::open(*handle), Ok(Some(_))))
+            .map(Volume::Ext2),
+    );
+    Ok(volumes)
+}
+
+// GPT unique partition GUID from the HardDrive node of the volume's device path
+fn partition_guid(volume: Handle) -> Option<Guid> {
+    let device_path = unsafe {
+        boot::open_protocol::<DevicePath>(
+            OpenProtocolParams {
+                handle: volume,
+                agent: boot::image_handle(),
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    }
+    .ok()?;
+
+    device_path
+        .node_iter()
+        .filter_map(|node| match node.as_enum() {
+            Ok(DevicePathNodeEnum::MediaHardDrive(hard_drive)) => {
+                match hard_drive.partition_signature() {
+                    PartitionSignature::Guid(guid) => Some(guid),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .last()
+}
+
+fn volume_label(volume: Handle) -> Option<String> {
+    let mut file_system = unsafe {
+        boot::open_protocol::<SimpleFileSystem>(
+            OpenProtocolParams {
+                handle: volume,
+                agent: boot::image_handle(),
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    }
+    .ok()?;
+
+    file_system
+        .open_volume()
+        .ok()?
+        .get_boxed_info::<FileSystemInfo>()
+        .ok()
+        .map(|info| info.volume_label().to_string())
+}
+
+// where the kernel and init are read from; config, splash and boot.log stay on the loader volume
+pub fn find_kernel_volume(selector: &VolumeSelector, kernel_path: &str) -> BootResult<Volume> {
+    let volume = match selector {
+        VolumeSelector::Loader => loader_volume().map(Volume::Firmware)?,
+        // never searched for, DHCP alone can take seconds per interface
+        VolumeSelector::Tftp(server) => {
+            find_tftp_server(*server).map(|(nic, server)| Volume::Tftp { nic, server })?
+        }
+        _ => search_volumes(selector, kernel_path)?,
+    };
+
+    let partition = partition_guid(volume.handle());
+    unsafe {
+        BOOT_INFO.boot_partition_info = BootPartitionInfo {
+            partition_guid: partition.map(|guid| guid.to_bytes()).unwrap_or([0; 16]),
+        };
+    }
+    Ok(volume)
+}
+
+fn search_volumes(selector: &VolumeSelector, kernel_path: &str) -> BootResult<Volume> {
+    let volumes = volumes()?;
+    for (index, volume) in volumes.iter().enumerate() {
+        debug!(
+            "Volume {}: {}, label '{}', partition {}",
+            index,
+            match volume {
+                Volume::Firmware(_) => "firmware",
+                Volume::Ext2(_) => "ext2",
+                Volume::Tftp { .. } => "tftp",
+            },
+            volume.label().unwrap_or_default(),
+            partition_guid(volume.handle())
+                .map(|guid| guid.to_string())
+                .unwrap_or_else(|| "-".to_string())
+        );
+    }
+
+    let found = match selector {
+        // in partition order, not volume order
+        VolumeSelector::A9nPartition => find_a9n_partitions()?.iter().find_map(|partition| {
+            volumes
+                .iter()
+                .find(|volume| partition_guid(volume.handle()) == Some(partition.unique_guid))
+                .copied()
+        }),
+        _ => volumes.into_iter().find(|volume| match selector {
+            VolumeSelector::Loader | VolumeSelector::A9nPartition | VolumeSelector::Tftp(_) => {
+                false
+            }
+            VolumeSelector::Auto => volume.exists(kernel_path),
+            VolumeSelector::PartitionGuid(guid) => partition_guid(volume.handle()) == Some(*guid),
+            VolumeSelector::Label(label) => volume.label().as_deref() == Some(label),
+        }),
+    };
+
+    found
+        .inspect(|_| info!("Using volume {} for the kernel", selector))
+        .ok_or_else(|| BootError::VolumeNotFound {
+            selector: format!("{}", selector),
+        })
+}
@@ -0,0 +1,218 @@
+mod console_sink;
+pub use console_sink::*;
+
+mod file_sink;
+pub use file_sink::*;
+
+mod memory_sink;
+pub use memory_sink::*;
+
+use crate::{config, input, print};
+
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::vec;
+
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    // verbose in debug builds, quiet enough for everyday boots otherwise
+    pub const DEFAULT: Level = if cfg!(debug_assertions) {
+        Level::Debug
+    } else {
+        Level::Info
+    };
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "error" => Some(Level::Error),
+            "warn" | "warning" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            _ => None,
+        }
+    }
+
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => " WARN",
+            Level::Info => " INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+
+    pub fn ansi_color(&self) -> &'static str {
+        match self {
+            Level::Error => "\x1b[31m",
+            Level::Warn => "\x1b[33m",
+            Level::Info => "\x1b[32m",
+            Level::Debug => "\x1b[34m",
+        }
+    }
+}
+
+pub trait LogSink {
+    // most verbose level this sink accepts
+    fn level(&self) -> Level;
+    fn write(&mut self, level: Level, message: &str);
+    fn flush(&mut self) {}
+}
+
+// records above this are dropped before reaching any sink
+static mut MAX_LEVEL: Level = Level::DEFAULT;
+
+static mut SINKS: vec::Vec<Box<dyn LogSink>> = vec::Vec::new();
+// until the console sinks exist, records are echoed through print
+static mut CONSOLE_SINKS_READY: bool = false;
+
+#[allow(static_mut_refs)]
+fn sinks() -> &'static mut vec::Vec<Box<dyn LogSink>> {
+    unsafe { &mut SINKS }
+}
+
+pub fn register_sink(sink: Box<dyn LogSink>) {
+    sinks().push(sink);
+}
+
+// memory ring first, so it also captures everything logged before the consoles are up
+pub fn init_early() {
+    match MemorySink::new(Level::Debug) {
+        Ok(sink) => register_sink(Box::new(sink)),
+        Err(e) => crate::warn!("Failed to allocate the log ring buffer: {}", e),
+    }
+}
+
+pub fn enabled(level: Level) -> bool {
+    level <= unsafe { MAX_LEVEL }
+}
+
+pub fn set_max_level(level: Level) {
+    unsafe { MAX_LEVEL = level };
+}
+
+// config (and `loglevel=` in the load options) first, then `d` held during startup forces debug
+pub fn init_level() {
+    set_max_level(config::current().log_level);
+
+    if matches!(input::poll_key(), Some(input::Key::Char('d' | 'D'))) {
+        set_max_level(Level::Debug);
+        crate::info!("Debug logging enabled by key press");
+    }
+}
+
+// one sink per registered console, filtered by `log.<console>=<level>`
+pub fn init_console_sinks() {
+    for (index, name) in print::console_names().enumerate() {
+        let level = config::current().log_level_for(name);
+        register_sink(Box::new(ConsoleSink::new(index, level)));
+    }
+    unsafe { CONSOLE_SINKS_READY = true };
+}
+
+// `bootlog=yes`: rotate the previous logs and start a new boot.log with everything so far
+pub fn init_file_sink() {
+    let config = config::current();
+    if !config.bootlog {
+        return;
+    }
+
+    rotate_boot_logs(config.bootlog_keep);
+    register_sink(Box::new(FileSink::new(
+        config.log_level_for("file"),
+        memory_log_contents(),
+    )));
+}
+
+pub fn flush() {
+    for sink in sinks().iter_mut() {
+        sink.flush();
+    }
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, message: &str) {
+    if !enabled(level) {
+        return;
+    }
+
+    if !unsafe { CONSOLE_SINKS_READY } {
+        print::_print(format_args!(
+            "[{}{}\x1b[37m] {}\n",
+            level.ansi_color(),
+            level.tag(),
+            message
+        ));
+    }
+
+    for sink in sinks().iter_mut() {
+        if level <= sink.level() {
+            sink.write(level, message);
+        }
+    }
+}
+
+// plain "[ INFO] message\n", for sinks that don't understand ANSI colors
+pub fn format_plain(level: Level, message: &str) -> alloc::string::String {
+    format!("[{}] {}\n", level.tag(), message)
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {{
+        extern crate alloc;
+        let __message = alloc::format!($($arg)*);
+        $crate::log::_log($crate::log::Level::Info, __message.as_str());
+    }};
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {{
+        extern crate alloc;
+        let __message = alloc::format!($($arg)*);
+        $crate::log::_log($crate::log::Level::Warn, __message.as_str());
+    }};
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {{
+        extern crate alloc;
+        let __message = alloc::format!($($arg)*);
+        $crate::log::_log($crate::log::Level::Error, __message.as_str());
+    }};
+}
+
+// formatted only when debug logging is enabled at runtime
+#[cfg(not(feature = "strip-debug-log"))]
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        if $crate::log::enabled($crate::log::Level::Debug) {
+            extern crate alloc;
+            let __message = alloc::format!($($arg)*);
+            $crate::log::_log($crate::log::Level::Debug, __message.as_str());
+        }
+    }};
+}
+
+// compiled out entirely for size-sensitive builds
+#[cfg(feature = "strip-debug-log")]
+#[macro_export]
+macro_rules! debug {
+    // still type-check the arguments so they don't turn into unused variables
+    ($($arg:tt)*) => {{
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    }};
+}
@@ -0,0 +1,33 @@
+use crate::log::{Level, LogSink};
+use crate::print;
+
+extern crate alloc;
+use alloc::format;
+
+// forwards records to one of the consoles registered in print
+pub struct ConsoleSink {
+    console: usize,
+    level: Level,
+}
+
+impl ConsoleSink {
+    pub fn new(console: usize, level: Level) -> Self {
+        ConsoleSink { console, level }
+    }
+}
+
+impl LogSink for ConsoleSink {
+    fn level(&self) -> Level {
+        self.level
+    }
+
+    fn write(&mut self, level: Level, message: &str) {
+        let line = format!(
+            "[{}{}\x1b[37m] {}\n",
+            level.ansi_color(),
+            level.tag(),
+            message
+        );
+        print::write_console(self.console, &line);
+    }
+}
@@ -0,0 +1,56 @@
+use crate::loader::{rename_file, write_entire_file};
+use crate::log::{self, Level, LogSink};
+
+extern crate alloc;
+use alloc::format;
+use alloc::vec;
+
+pub const BOOT_LOG_PATH: &str = r"\a9nloader\boot.log";
+
+// collects plain-text records and writes them to the ESP on flush;
+// writing per record would be far too slow on most firmware
+pub struct FileSink {
+    buffer: vec::Vec<u8>,
+    level: Level,
+}
+
+impl FileSink {
+    // `history` is what was logged before the sink existed, e.g. the memory ring
+    pub fn new(level: Level, history: vec::Vec<u8>) -> Self {
+        FileSink {
+            buffer: history,
+            level,
+        }
+    }
+}
+
+impl LogSink for FileSink {
+    fn level(&self) -> Level {
+        self.level
+    }
+
+    fn write(&mut self, level: Level, message: &str) {
+        self.buffer
+            .extend_from_slice(log::format_plain(level, message).as_bytes());
+    }
+
+    fn flush(&mut self) {
+        // nowhere left to report a failure to, the log is what failed
+        let _ = write_entire_file(BOOT_LOG_PATH, &self.buffer);
+    }
+}
+
+// boot.log -> boot.log.1 -> ... -> boot.log.<keep>, the oldest is replaced
+pub fn rotate_boot_logs(keep: usize) {
+    for index in (1..=keep).rev() {
+        let from = match index {
+            1 => BOOT_LOG_PATH.into(),
+            _ => format!("{}.{}", BOOT_LOG_PATH, index - 1),
+        };
+        let to = format!("{}.{}", BOOT_LOG_PATH, index);
+
+        if let Err(e) = rename_file(&from, &to) {
+            crate::warn!("Failed to rotate {} to {}: {}", from, to, e);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::loader::{BOOT_INFO, LogBufferInfo};
+use crate::log::{self, Level, LogSink};
+use crate::util::*;
+
+extern crate alloc;
+use alloc::vec;
+
+use uefi::boot::{self, MemoryType};
+
+// 64 KiB of plain-text log for the kernel to replay
+pub const LOG_BUFFER_PAGES: usize = 16;
+
+// ring of plain-text records in RESERVED pages, described by BootInfo::log_buffer_info
+pub struct MemorySink {
+    buffer: &'static mut [u8],
+    // offset of the oldest byte and number of valid bytes
+    start: usize,
+    length: usize,
+    level: Level,
+}
+
+impl MemorySink {
+    pub fn new(level: Level) -> BootResult<Self> {
+        let size = LOG_BUFFER_PAGES * EFI_PAGE_SIZE;
+        let address = boot::allocate_pages(
+            boot::AllocateType::AnyPages,
+            MemoryType::RESERVED,
+            LOG_BUFFER_PAGES,
+        )
+        .map_err(|e| BootError::AllocationFailed {
+            address: None,
+            pages: LOG_BUFFER_PAGES,
+            memory_type: MemoryType::RESERVED,
+            status: e.status(),
+        })?;
+
+        let buffer = unsafe {
+            core::ptr::write_bytes(address.as_ptr(), 0, size);
+            core::slice::from_raw_parts_mut(address.as_ptr(), size)
+        };
+
+        let sink = MemorySink {
+            buffer,
+            start: 0,
+            length: 0,
+            level,
+        };
+        sink.publish();
+        Ok(sink)
+    }
+
+    // keep BootInfo in sync after every record, there is no later chance once we jump
+    fn publish(&self) {
+        unsafe {
+            BOOT_INFO.log_buffer_info = LogBufferInfo {
+                address: self.buffer.as_ptr() as usize,
+                size: self.buffer.len(),
+                start: self.start,
+                length: self.length,
+            };
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        let size = self.buffer.len();
+        for byte in bytes {
+            let end = (self.start + self.length) % size;
+            self.buffer[end] = *byte;
+            if self.length < size {
+                self.length += 1;
+            } else {
+                // full, overwrite the oldest byte
+                self.start = (self.start + 1) % size;
+            }
+        }
+    }
+}
+
+impl LogSink for MemorySink {
+    fn level(&self) -> Level {
+        self.level
+    }
+
+    fn write(&mut self, level: Level, message: &str) {
+        self.push(log::format_plain(level, message).as_bytes());
+        self.publish();
+    }
+}
+
+// oldest first, empty if the ring was never allocated
+pub fn memory_log_contents() -> vec::Vec<u8> {
+    let info = unsafe { BOOT_INFO.log_buffer_info };
+    if info.address == 0 {
+        return vec::Vec::new();
+    }
+
+    let buffer = unsafe { core::slice::from_raw_parts(info.address as *const u8, info.size) };
+    let end = info.start + info.length;
+    let mut contents = vec::Vec::with_capacity(info.length);
+    contents.extend_from_slice(&buffer[info.start..end.min(info.size)]);
+    if end > info.size {
+        contents.extend_from_slice(&buffer[..end - info.size]);
+    }
+    contents
+}
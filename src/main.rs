@@ -3,9 +3,14 @@
 #![allow(dead_code)]
 
 mod screen;
-use screen::Screen;
+mod config;
+mod error;
 mod gui;
+mod input;
 mod loader;
+mod log;
+mod menu;
+mod panic;
 mod print;
 mod util;
 
@@ -14,15 +19,49 @@ use uefi::prelude::*;
 #[entry]
 fn main() -> Status {
     uefi_init();
+    log::init_early();
+    config::load();
+    log::init_level();
+    gui::load_splash();
     gui_init();
+    print::init_consoles();
+    log::init_console_sinks();
+    log::init_file_sink();
 
     log_a9nloader_info();
+    menu::boot_menu();
+    loader::select_slot();
 
-    loader::run().unwrap_or_else(|e| {
+    // loader::run only comes back on failure
+    loop {
+        let Err(e) = loader::run() else {
+            return Status::SUCCESS;
+        };
         error!("Failed to run loader: {}", e);
-    });
+        // keep the evidence
+        log::flush();
+
+        if loader::fall_back_to_good_slot() {
+            continue;
+        }
+
+        match gui::fatal_screen(&e, true) {
+            gui::FatalAction::Retry => {
+                gui::draw_boot_screen();
+                print::clear();
+                info!("Retrying...");
+            }
+            gui::FatalAction::Exit => return failure_status(&e),
+        }
+    }
+}
 
-    Status::SUCCESS
+// never report success to the firmware for a boot that didn't happen
+fn failure_status(error: &util::BootError) -> Status {
+    match error.status() {
+        status if status.is_error() => status,
+        _ => Status::ABORTED,
+    }
 }
 
 fn uefi_init() {
@@ -42,27 +81,11 @@ const A9NLOADER_LOGO: &str = r#"
 "#;
 
 fn gui_init() {
-    screen::init_screen();
-    gui::draw_bmp(gui::A9N_LOADER_SPLASH_BMP, 0, 0);
-
-    let screen = screen::current_screen();
-    let width = screen.width();
-    let height = screen.height();
-
-    for y in 80..height {
-        for x in 0..width {
-            screen.draw_pixel(
-                x,
-                y,
-                screen::Color {
-                    red: 0x14,
-                    green: 0x14,
-                    blue: 0x14,
-                    alpha: 0xff,
-                },
-            );
-        }
+    if !screen::init_screen() {
+        return;
     }
+
+    gui::draw_boot_screen();
 }
 
 fn log_a9nloader_info() {
@@ -0,0 +1,108 @@
+use crate::gui::Cursor;
+use crate::input::{self, Key};
+use crate::{config, gui, info, loader, print, println, warn};
+
+extern crate alloc;
+use alloc::format;
+use alloc::string::String;
+
+// A9N first, then the other A9N entries and the chainload entries; returns when A9N should
+// boot. skipped entirely if config has no entries besides A9N
+pub fn boot_menu() {
+    let config = config::current();
+    if config.entries.is_empty() && config.chainload.is_empty() {
+        return;
+    }
+
+    let count = 1 + config.entries.len() + config.chainload.len();
+    let mut selected = 0;
+    // counts down until the first key press, 0 waits for a choice
+    let mut timeout = Some(config.menu_timeout).filter(|seconds| *seconds > 0);
+    let mut status: Option<String> = None;
+
+    input::flush_keys();
+    draw(selected, timeout, status.as_deref());
+    loop {
+        let key = match timeout {
+            Some(seconds) => match input::wait_key_timeout(1000) {
+                Some(key) => {
+                    timeout = None;
+                    draw_countdown(count, timeout);
+                    key
+                }
+                None if seconds > 1 => {
+                    timeout = Some(seconds - 1);
+                    draw_countdown(count, timeout);
+                    continue;
+                }
+                None => break,
+            },
+            None => input::wait_key(),
+        };
+
+        // PageUp/PageDown page through the log behind the menu
+        if print::handle_scroll_key(key) {
+            continue;
+        }
+
+        match key {
+            Key::Up => selected = selected.checked_sub(1).unwrap_or(count - 1),
+            Key::Down => selected = (selected + 1) % count,
+            Key::Enter if selected == 0 => break,
+            Key::Enter if selected <= config.entries.len() => {
+                let entry = &config.entries[selected - 1];
+                info!("Booting {} from {}", entry.title, entry.volume);
+                config::set_volume(entry.volume.clone());
+                break;
+            }
+            Key::Enter => {
+                let entry = &config.chainload[selected - 1 - config.entries.len()];
+                status = match loader::chainload(&entry.path, entry.options.as_deref()) {
+                    Ok(()) => Some(format!("{} exited", entry.title)),
+                    Err(e) => {
+                        warn!("Failed to chainload {}: {}", entry.title, e);
+                        Some(format!("{} failed: {}", entry.title, e))
+                    }
+                };
+                // the child may have drawn all over the screen
+                gui::draw_boot_screen();
+            }
+            _ => continue,
+        }
+
+        draw(selected, timeout, status.as_deref());
+    }
+
+    print::clear();
+}
+
+fn draw(selected: usize, timeout: Option<usize>, status: Option<&str>) {
+    print::clear();
+    println!("Boot menu (Up/Down, Enter to boot):");
+
+    let config = config::current();
+    let titles = core::iter::once("A9N")
+        .chain(config.entries.iter().map(|e| e.title.as_str()))
+        .chain(config.chainload.iter().map(|e| e.title.as_str()));
+    for (index, title) in titles.enumerate() {
+        println!("{} {}", if index == selected { ">" } else { " " }, title);
+    }
+
+    if let Some(seconds) = timeout {
+        println!("\nBooting A9N in {} s", seconds);
+    }
+    if let Some(status) = status {
+        println!("\n{}", status);
+    }
+}
+
+// only the countdown line below the entries, so a tick doesn't push another copy of the
+// menu into the scrollback; None blanks it
+fn draw_countdown(count: usize, timeout: Option<usize>) {
+    // title row, the entries, an empty row
+    print::set_cursor(&Cursor { x: 0, y: count + 2 });
+    let line = timeout
+        .map(|seconds| format!("Booting A9N in {} s", seconds))
+        .unwrap_or_default();
+    print!("{:<32}", line);
+}
@@ -0,0 +1,46 @@
+use crate::gui::{self, FatalAction};
+use crate::util::BootError;
+use crate::{error, log};
+
+extern crate alloc;
+use alloc::string::ToString;
+
+use uefi::Status;
+use uefi::boot;
+
+// a panic while showing the fatal screen must not recurse into it
+static mut PANICKING: bool = false;
+
+// same check uefi's own handler does, exit_boot_services clears the pointer
+fn boot_services_active() -> bool {
+    uefi::table::system_table_raw()
+        .is_some_and(|table| !unsafe { table.as_ref() }.boot_services.is_null())
+}
+
+#[panic_handler]
+fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+    if boot_services_active() && !unsafe { PANICKING } {
+        unsafe { PANICKING = true };
+        error!("{}", info);
+        log::flush();
+
+        let error = BootError::Panic {
+            message: info.message().to_string(),
+        };
+        if gui::fatal_screen(&error, false) == FatalAction::Exit {
+            unsafe {
+                boot::exit(
+                    boot::image_handle(),
+                    Status::ABORTED,
+                    0,
+                    core::ptr::null_mut(),
+                )
+            }
+        }
+    }
+
+    // after exit_boot_services (or panicking again) there is nothing left to talk to
+    loop {
+        core::hint::spin_loop();
+    }
+}
@@ -1,127 +1,135 @@
-use crate::screen;
-use core::fmt::Write;
+use crate::gui::{Console, Cursor, GraphicalConsole, SerialConsole, TextConsole};
+use crate::input::Key;
+use crate::{config, screen};
 
 extern crate alloc;
+use alloc::boxed::Box;
+use alloc::string::String;
 use alloc::vec;
 
-use embedded_graphics::{
-    mono_font::{MonoTextStyle, ascii::*},
-    pixelcolor::Rgb888,
-    prelude::*,
-    primitives::Rectangle,
-};
-use embedded_text::{
-    TextBox,
-    plugin::ansi::Ansi,
-    style::{HeightMode, TextBoxStyle, TextBoxStyleBuilder},
-};
-
-struct VirtualConsole<'a> {
-    textbox_style: TextBoxStyle,
-    character_style: MonoTextStyle<'a, Rgb888>,
-    cursor: Point,
-    line_buffer: vec::Vec<u8>,
-}
+use core::fmt::Write;
 
-const CONSOLE_WIDTH_OFFSET: i32 = 10;
+use embedded_graphics::mono_font::ascii::FONT_6X12;
 
-fn calculate_console_height_offset() -> i32 {
-    let (_w, h) =
-        crate::gui::get_bmp_dimensions(crate::gui::A9N_LOADER_SPLASH_BMP).unwrap_or((0, 0));
-    h as i32 + 10
+// every output `print!` and the log macros go to
+static mut CONSOLES: vec::Vec<Box<dyn Console>> = vec::Vec::new();
+
+#[allow(static_mut_refs)]
+fn consoles() -> &'static mut vec::Vec<Box<dyn Console>> {
+    unsafe { &mut CONSOLES }
 }
 
-impl core::fmt::Write for VirtualConsole<'_> {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        #[allow(static_mut_refs)]
-        unsafe {
-            if let Some(screen) = screen::SCREEN.as_mut() {
-                let line_height = self.character_style.font.character_size.height as i32;
-                let screen_height = screen.bounding_box().size.height as i32;
-
-                // split lines
-                for line in s.lines() {
-                    // scroll
-                    if self.cursor.y + line_height > screen_height {
-                        screen.clear(Rgb888::BLACK).ok(); // エラーは無視
-                        // clear lines
-
-                        // reset cursor position
-                        self.cursor.y = calculate_console_height_offset();
-
-                        // redraw splash
-                        crate::gui::draw_bmp(crate::gui::A9N_LOADER_SPLASH_BMP, 0, 0);
-                    }
-
-                    if !line.is_empty() {
-                        let bounds = Rectangle::new(
-                            self.cursor,
-                            // Size::new(screen.size().width - self.cursor.x as u32, 0),
-                            Size::new(
-                                screen.size().width,
-                                self.character_style.font.character_size.height,
-                            ),
-                        );
-
-                        let textbox = TextBox::with_textbox_style(
-                            line,
-                            bounds,
-                            self.character_style,
-                            self.textbox_style,
-                        )
-                        .add_plugin(Ansi::new());
-
-                        let _ = textbox.draw(screen);
-                    }
-
-                    self.cursor.y += line_height;
-                }
-                screen::Screen::flush_all(screen);
-            }
+pub fn register_console(console: Box<dyn Console>) {
+    consoles().push(console);
+}
+
+// (re)build the console set from `console=` once config and screen are up
+pub fn init_consoles() {
+    let selection = config::current().console;
+    consoles().clear();
+
+    if selection.gop && screen::current_screen().is_some() {
+        register_console(Box::new(GraphicalConsole::new(
+            &FONT_6X12,
+            config::current().scrollback,
+        )));
+    }
+
+    if selection.serial {
+        match SerialConsole::new() {
+            Some(serial) => register_console(Box::new(serial)),
+            None => crate::warn!("No serial port found for the serial console"),
         }
+    }
 
-        Ok(())
+    // never end up without any output
+    if selection.conout || consoles().is_empty() {
+        register_console(Box::new(TextConsole::new()));
     }
 }
 
-static mut VIRTUAL_CONSOLE: Option<VirtualConsole<'static>> = None;
-
 #[doc(hidden)]
 pub fn _print(args: core::fmt::Arguments) {
-    unsafe {
-        #[allow(static_mut_refs)]
-        if VIRTUAL_CONSOLE.is_none() {
-            let character_style = MonoTextStyle::new(&FONT_6X12, Rgb888::WHITE);
-            let line_height = character_style.font.character_size.height as i32;
-            let textbox_style = TextBoxStyleBuilder::new()
-                .height_mode(HeightMode::Exact(
-                    embedded_text::style::VerticalOverdraw::Hidden,
-                ))
-                .line_height(embedded_graphics::text::LineHeight::Pixels(
-                    line_height as u32,
-                ))
-                .trailing_spaces(false)
-                .paragraph_spacing(0)
-                .build();
-
-            let line_buffer = vec![0; 512];
-
-            VIRTUAL_CONSOLE = Some(VirtualConsole {
-                textbox_style,
-                character_style,
-                cursor: Point::new(CONSOLE_WIDTH_OFFSET, calculate_console_height_offset()),
-                line_buffer,
-            });
-        }
-
-        #[allow(static_mut_refs)]
-        let _ = VIRTUAL_CONSOLE.as_mut().map(|virtual_console| {
-            let _ = virtual_console.write_fmt(args);
-        });
-
+    // before init_consoles() only ConOut is available
+    if consoles().is_empty() {
         uefi::system::with_stdout(|stdout| {
-            stdout.write_fmt(args).expect("Failed to write to stdout");
+            let _ = stdout.write_fmt(args);
         });
+        return;
+    }
+
+    let mut text = String::new();
+    let _ = text.write_fmt(args);
+    for console in consoles().iter_mut() {
+        let _ = console.write_str(&text);
+    }
+}
+
+pub fn console_names() -> impl Iterator<Item = &'static str> {
+    consoles().iter().map(|console| console.name())
+}
+
+// write to a single console, used by the per-console log sinks
+pub fn write_console(index: usize, text: &str) {
+    if let Some(console) = consoles().get_mut(index) {
+        let _ = console.write_str(text);
+    }
+}
+
+pub fn clear() {
+    for console in consoles().iter_mut() {
+        let _ = console.clear();
+    }
+}
+
+// position every console's cursor, e.g. for menus drawn at fixed rows
+pub fn set_cursor(cursor: &Cursor) {
+    for console in consoles().iter_mut() {
+        let _ = console.set_cursor(cursor);
+    }
+}
+
+// smallest (columns, rows) of all consoles, so a layout fits everywhere
+pub fn console_size() -> (usize, usize) {
+    consoles()
+        .iter()
+        .filter_map(|console| console.size().ok())
+        .fold((usize::MAX, usize::MAX), |(columns, rows), (c, r)| {
+            (columns.min(c), rows.min(r))
+        })
+}
+
+// page through the scrollback, returns false if the key is not a scroll key
+pub fn handle_scroll_key(key: Key) -> bool {
+    let (_, rows) = console_size();
+    let page = rows.saturating_sub(1).clamp(1, isize::MAX as usize) as isize;
+    let rows = match key {
+        Key::PageUp => page,
+        Key::PageDown => -page,
+        Key::Home => isize::MAX,
+        Key::End => isize::MIN,
+        _ => return false,
+    };
+
+    let mut handled = false;
+    for console in consoles().iter_mut() {
+        handled |= console.scroll_view(rows);
+    }
+    handled
+}
+
+// wait for a key, PageUp/PageDown page through the log meanwhile
+pub fn pause(message: &str) -> Key {
+    crate::println!("{}", message);
+    crate::input::flush_keys();
+    loop {
+        let key = crate::input::wait_key();
+        if !handle_scroll_key(key) {
+            for console in consoles().iter_mut() {
+                console.scroll_view(isize::MIN);
+            }
+            return key;
+        }
     }
 }
 
@@ -150,68 +158,3 @@ macro_rules! println {
         $crate::print::_print(core::format_args!("{}", __line.as_str()));
     }};
 }
-
-#[macro_export]
-macro_rules! info {
-    ($fmt:literal $(, $($arg:tt)+)?) => {{
-        extern crate alloc;
-        let __line = alloc::format!(concat!("[\x1b[32m INFO\x1b[37m] ", $fmt, "\n") $(, $($arg)+)?);
-        $crate::print::_print(core::format_args!("{}", __line.as_str()));
-    }};
-    ($($arg:tt)*) => {{
-        let __payload = alloc::format!($($arg)*);
-        let __line = alloc::format!("[\x1b[32m INFO\x1b[37m] {}\n", __payload);
-        $crate::print::_print(core::format_args!("{}", __line.as_str()));
-    }};
-}
-
-#[macro_export]
-macro_rules! warn {
-    ($fmt:literal $(, $($arg:tt)+)?) => {{
-        extern crate alloc;
-        let __line = alloc::format!(concat!("[\x1b[33m WARN\x1b[37m] ", $fmt, "\n") $(, $($arg)+)?);
-        $crate::print::_print(core::format_args!("{}", __line.as_str()));
-    }};
-    ($($arg:tt)*) => {{
-        let __payload = alloc::format!($($arg)*);
-        let __line = alloc::format!("[\x1b[33m WARN\x1b[37m] {}\n", __payload);
-        $crate::print::_print(core::format_args!("{}", __line.as_str()));
-    }};
-}
-
-#[macro_export]
-macro_rules! error {
-    ($fmt:literal $(, $($arg:tt)+)?) => {{
-        extern crate alloc;
-        let __line = alloc::format!(concat!("[\x1b[31mERROR\x1b[37m] ", $fmt, "\n") $(, $($arg)+)?);
-        $crate::print::_print(core::format_args!("{}", __line.as_str()));
-    }};
-    ($($arg:tt)*) => {{
-        let __payload = alloc::format!($($arg)*);
-        let __line = alloc::format!("[\x1b[31mERROR\x1b[37m] {}\n", __payload);
-        $crate::print::_print(core::format_args!("{}", __line.as_str()));
-    }};
-}
-
-// debug build only
-#[cfg(debug_assertions)]
-#[macro_export]
-macro_rules! debug {
-    ($fmt:literal $(, $($arg:tt)+)?) => {{
-        extern crate alloc;
-        let __line = alloc::format!(concat!("[\x1b[34mDEBUG\x1b[37m] ", $fmt, "\n") $(, $($arg)+)?);
-        $crate::print::_print(core::format_args!("{}", __line.as_str()));
-    }};
-    ($($arg:tt)*) => {{
-        let __payload = alloc::format!($($arg)*);
-        let __line = alloc::format!("[\x1b[34mDEBUG\x1b[37m] {}\n", __payload);
-        $crate::print::_print(core::format_args!("{}", __line.as_str()));
-    }};
-}
-
-#[cfg(not(debug_assertions))]
-#[macro_export]
-macro_rules! debug {
-    ($fmt:literal $(, $($arg:tt)+)?) => {{}};
-    ($($arg:tt)*) => {{}};
-}
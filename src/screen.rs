@@ -5,3 +5,6 @@ pub use screen::*;
 // specific implementation
 mod vga_screen;
 pub use vga_screen::*;
+
+mod video_mode;
+pub use video_mode::*;
@@ -1,5 +1,6 @@
 // interface for common screen
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct Color {
     pub red: u8,
     pub green: u8,
@@ -7,6 +8,38 @@ pub struct Color {
     pub alpha: u8,
 }
 
+impl Color {
+    pub const fn rgb(red: u8, green: u8, blue: u8) -> Self {
+        Color {
+            red,
+            green,
+            blue,
+            alpha: 0xff,
+        }
+    }
+
+    // source-over with straight (non-premultiplied) alpha
+    pub fn blend_over(self, destination: Color) -> Color {
+        match self.alpha {
+            0xff => return self,
+            0 => return destination,
+            _ => {}
+        }
+
+        let alpha = self.alpha as u16;
+        let mix = |source: u8, destination: u8| -> u8 {
+            ((source as u16 * alpha + destination as u16 * (0xff - alpha) + 0x7f) / 0xff) as u8
+        };
+
+        Color {
+            red: mix(self.red, destination.red),
+            green: mix(self.green, destination.green),
+            blue: mix(self.blue, destination.blue),
+            alpha: (alpha + (destination.alpha as u16 * (0xff - alpha) + 0x7f) / 0xff) as u8,
+        }
+    }
+}
+
 pub enum Mode {
     BGRA,
     RGBA,
@@ -27,6 +60,42 @@ pub trait Screen {
     fn draw_pixel(&mut self, x: usize, y: usize, color: Color);
     fn pixel_at(&mut self, x: usize, y: usize) -> Color;
 
+    // composite `color` over what is already in the back buffer
+    fn blend_pixel(&mut self, x: usize, y: usize, color: Color) {
+        if x >= self.width() || y >= self.height() {
+            return;
+        }
+
+        match color.alpha {
+            0 => {}
+            0xff => self.draw_pixel(x, y, color),
+            _ => {
+                let destination = self.pixel_at(x, y);
+                self.draw_pixel(x, y, color.blend_over(destination));
+            }
+        }
+    }
+
+    fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        for row in y..y + height {
+            for column in x..x + width {
+                self.draw_pixel(column, row, color);
+            }
+        }
+    }
+
+    // move the full-width rows [y + distance, y + height) up to y and fill the freed rows
+    fn scroll_up(&mut self, y: usize, height: usize, distance: usize, fill: Color) {
+        let distance = distance.min(height);
+        for row in y..y + height - distance {
+            for column in 0..self.width() {
+                let color = self.pixel_at(column, row + distance);
+                self.draw_pixel(column, row, color);
+            }
+        }
+        self.fill_rect(0, y + height - distance, self.width(), distance, fill);
+    }
+
     fn flush(&mut self, x: usize, y: usize);
     fn flush_rect(&mut self, x: usize, y: usize, width: usize, height: usize);
     fn flush_all(&mut self);
@@ -1,9 +1,11 @@
 use crate::screen;
+use crate::warn;
 
 use uefi::boot;
 use uefi::proto::console::gop::BltPixel;
 use uefi::proto::console::gop::PixelFormat;
 
+use crate::config;
 use crate::loader;
 
 use embedded_graphics::draw_target::DrawTarget;
@@ -23,12 +25,15 @@ pub struct VgaScreen {
 }
 
 impl VgaScreen {
-    pub fn new() -> Self {
+    // returns None on machines without a usable GOP (serial-only, `-nographic`, ...)
+    pub fn new() -> Option<Self> {
         let gop_handle =
-            boot::get_handle_for_protocol::<uefi::proto::console::gop::GraphicsOutput>().unwrap();
+            boot::get_handle_for_protocol::<uefi::proto::console::gop::GraphicsOutput>().ok()?;
         let mut gop =
             boot::open_protocol_exclusive::<uefi::proto::console::gop::GraphicsOutput>(gop_handle)
-                .unwrap();
+                .ok()?;
+
+        screen::select_video_mode(&mut gop, gop_handle, config::current().video);
 
         let current_mode = gop.current_mode_info();
         let (width, height) = current_mode.resolution();
@@ -36,8 +41,8 @@ impl VgaScreen {
         back_buffer.resize(width * height, BltPixel::new(0, 0, 0));
 
         // configure the framebuffer information to boot_info for the kernel->user
-        let (r, g, b) = match current_mode.pixel_format() {
-            PixelFormat::Rgb => (
+        let color_fields = match current_mode.pixel_format() {
+            PixelFormat::Rgb => Some((
                 loader::ColorField {
                     position: 0,
                     size: 8,
@@ -50,8 +55,8 @@ impl VgaScreen {
                     position: 16,
                     size: 8,
                 },
-            ),
-            PixelFormat::Bgr => (
+            )),
+            PixelFormat::Bgr => Some((
                 loader::ColorField {
                     position: 16,
                     size: 8,
@@ -64,11 +69,8 @@ impl VgaScreen {
                     position: 0,
                     size: 8,
                 },
-            ),
-            PixelFormat::Bitmask => {
-                let bit_mask = current_mode
-                    .pixel_bitmask()
-                    .expect("Failed to get pixel bitmask");
+            )),
+            PixelFormat::Bitmask => current_mode.pixel_bitmask().map(|bit_mask| {
                 let to_color_field = |mask: u32| -> loader::ColorField {
                     let position = mask.trailing_zeros() as u8;
                     let size = (mask.count_ones()) as u8;
@@ -79,13 +81,12 @@ impl VgaScreen {
                 let b = to_color_field(bit_mask.blue);
 
                 (r, g, b)
-            }
-            PixelFormat::BltOnly => {
-                panic!("BltOnly pixel format is not supported for direct framebuffer access");
-            }
+            }),
+            // we can still draw through blt, but the kernel has no linear framebuffer to use
+            PixelFormat::BltOnly => None,
         };
 
-        let frame_buffer_info = loader::FramebufferInfo {
+        let frame_buffer_info = color_fields.map(|(r, g, b)| loader::FramebufferInfo {
             address: gop.frame_buffer().as_mut_ptr() as usize,
             width: width as u32,
             height: height as u32,
@@ -98,20 +99,19 @@ impl VgaScreen {
                 position: 24,
                 size: 8,
             },
-        };
-
-        let serialized_info = frame_buffer_info.serialize();
+        });
 
-        unsafe {
-            loader::BOOT_INFO.arch_info[1..14].copy_from_slice(&serialized_info);
+        if frame_buffer_info.is_none() {
+            warn!("GOP mode has no linear framebuffer (BltOnly), the kernel will run headless");
         }
+        loader::set_framebuffer_info(frame_buffer_info.as_ref());
 
-        VgaScreen {
+        Some(VgaScreen {
             screen_width: width,
             screen_height: height,
             gop,
             back_buffer,
-        }
+        })
     }
 
     #[inline]
@@ -257,6 +257,20 @@ impl screen::Screen for VgaScreen {
         // let _ = self.present_pixel(x, y);
     }
 
+    fn scroll_up(&mut self, y: usize, height: usize, distance: usize, fill: screen::Color) {
+        let bottom = (y + height).min(self.screen_height);
+        if y >= bottom {
+            return;
+        }
+        let distance = distance.min(bottom - y);
+
+        let start = self.index(0, y);
+        let end = self.index(0, bottom);
+        let shift = distance * self.screen_width;
+        self.back_buffer.copy_within(start + shift..end, start);
+        self.back_buffer[end - shift..end].fill(Self::to_blt(fill));
+    }
+
     fn flush(&mut self, x: usize, y: usize) {
         let _ = self.present_pixel(x, y);
     }
@@ -326,16 +340,25 @@ impl DrawTarget for VgaScreen {
     }
 }
 
-pub fn init_screen() {
+// returns false if no screen is available, everything drawing-related is skipped then
+pub fn init_screen() -> bool {
+    let screen = VgaScreen::new();
+    if screen.is_none() {
+        warn!("No usable GOP found, falling back to text output");
+        loader::set_framebuffer_info(None);
+    }
+
     unsafe {
-        SCREEN = Some(VgaScreen::new());
+        SCREEN = screen;
+        #[allow(static_mut_refs)]
+        SCREEN.is_some()
     }
 }
 
 pub static mut SCREEN: Option<VgaScreen> = None;
-pub fn current_screen() -> &'static mut VgaScreen {
+pub fn current_screen() -> Option<&'static mut VgaScreen> {
     #[allow(static_mut_refs)]
     unsafe {
-        SCREEN.as_mut().unwrap()
+        SCREEN.as_mut()
     }
 }
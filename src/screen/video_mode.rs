@@ -0,0 +1,156 @@
+// GOP mode selection (`video=` in config / load options)
+
+use crate::config::VideoMode;
+use crate::input::{self, Key};
+use crate::{info, println, warn};
+
+extern crate alloc;
+use alloc::vec;
+
+use uefi::Handle;
+use uefi::boot::{self, OpenProtocolAttributes, OpenProtocolParams};
+use uefi::proto::console::gop::{GraphicsOutput, Mode, PixelFormat};
+use uefi::proto::unsafe_protocol;
+
+// EFI_EDID_ACTIVE_PROTOCOL
+#[repr(C)]
+#[unsafe_protocol("bd8c1056-9f36-44ec-92a8-a6337f817986")]
+struct EdidActive {
+    size_of_edid: u32,
+    edid: *const u8,
+}
+
+pub fn select_video_mode(gop: &mut GraphicsOutput, gop_handle: Handle, request: VideoMode) {
+    if request == VideoMode::Current {
+        return;
+    }
+
+    // modes without a linear framebuffer are useless for the kernel
+    let modes: vec::Vec<Mode> = gop
+        .modes()
+        .filter(|mode| mode.info().pixel_format() != PixelFormat::BltOnly)
+        .collect();
+
+    let target = match request {
+        VideoMode::Current => None,
+        VideoMode::Max => find_max_mode(&modes),
+        VideoMode::Native => native_resolution(gop_handle)
+            .and_then(|(width, height)| find_mode(&modes, width, height))
+            .or_else(|| {
+                warn!("Native resolution unavailable, using the largest mode");
+                find_max_mode(&modes)
+            }),
+        VideoMode::Resolution { width, height } => find_mode(&modes, width, height),
+        VideoMode::Menu => choose_mode_interactive(gop, &modes),
+    };
+
+    let Some(mode) = target else {
+        warn!(
+            "Requested video mode {:?} is not available, keeping the current mode",
+            request
+        );
+        return;
+    };
+
+    let (width, height) = mode.info().resolution();
+    if gop.current_mode_info().resolution() == (width, height) {
+        return;
+    }
+
+    match gop.set_mode(mode) {
+        Ok(_) => info!("Video mode set to {}x{}", width, height),
+        Err(e) => warn!(
+            "Failed to set video mode {}x{}: {}, keeping the current mode",
+            width, height, e
+        ),
+    }
+}
+
+fn find_mode(modes: &[Mode], width: usize, height: usize) -> Option<&Mode> {
+    modes
+        .iter()
+        .find(|mode| mode.info().resolution() == (width, height))
+}
+
+fn find_max_mode(modes: &[Mode]) -> Option<&Mode> {
+    modes.iter().max_by_key(|mode| {
+        let (width, height) = mode.info().resolution();
+        width * height
+    })
+}
+
+// preferred timing from the first detailed timing descriptor of the active EDID
+fn native_resolution(gop_handle: Handle) -> Option<(usize, usize)> {
+    let edid_active = unsafe {
+        boot::open_protocol::<EdidActive>(
+            OpenProtocolParams {
+                handle: gop_handle,
+                agent: boot::image_handle(),
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    }
+    .ok()?;
+
+    if edid_active.size_of_edid < 128 || edid_active.edid.is_null() {
+        return None;
+    }
+
+    let edid = unsafe { core::slice::from_raw_parts(edid_active.edid, 128) };
+    let descriptor = &edid[54..72];
+
+    // a zero pixel clock marks a display descriptor instead of a timing
+    if descriptor[0] == 0 && descriptor[1] == 0 {
+        return None;
+    }
+
+    let width = descriptor[2] as usize | ((descriptor[4] as usize & 0xf0) << 4);
+    let height = descriptor[5] as usize | ((descriptor[7] as usize & 0xf0) << 4);
+
+    info!("EDID preferred resolution: {}x{}", width, height);
+    Some((width, height))
+}
+
+// the screen is not up yet, so the list goes to ConOut
+fn choose_mode_interactive<'a>(gop: &GraphicsOutput, modes: &'a [Mode]) -> Option<&'a Mode> {
+    if modes.is_empty() {
+        return None;
+    }
+
+    let current = gop.current_mode_info().resolution();
+    let mut selected = modes
+        .iter()
+        .position(|mode| mode.info().resolution() == current)
+        .unwrap_or(0);
+
+    input::flush_keys();
+    loop {
+        uefi::system::with_stdout(|stdout| {
+            let _ = stdout.clear();
+        });
+        println!("Select video mode (Up/Down, Enter to confirm, Esc to keep current):");
+        for (i, mode) in modes.iter().enumerate() {
+            let (width, height) = mode.info().resolution();
+            println!(
+                "{} {:>5} x {:<5}{}",
+                if i == selected { ">" } else { " " },
+                width,
+                height,
+                if (width, height) == current {
+                    " (current)"
+                } else {
+                    ""
+                }
+            );
+        }
+
+        match input::wait_key() {
+            Key::Up => selected = selected.checked_sub(1).unwrap_or(modes.len() - 1),
+            Key::Down => selected = (selected + 1) % modes.len(),
+            Key::Enter => return modes.get(selected),
+            Key::Escape => return None,
+            _ => {}
+        }
+    }
+}
@@ -1,7 +1,8 @@
-use uefi::Error;
 use uefi::Status;
 
-pub type BootResult<T> = Result<T, Error>;
+pub use crate::error::{BootError, ElfViolation, ResultExt};
+
+pub type BootResult<T> = Result<T, BootError>;
 
 pub const EFI_PAGE_SIZE: usize = 4096;
 pub const HIGHER_HALF_MASK: usize = 0xFFFF_8000_0000_0000;
@@ -18,8 +19,8 @@ pub fn bytes_to_pages_rounded(bytes: usize) -> usize {
 }
 
 #[inline(always)]
-pub fn uefi_error(status: Status) -> Error {
-    Error::from(status)
+pub fn uefi_error(status: Status) -> BootError {
+    BootError::Uefi(status)
 }
 
 #[inline(always)]
@@ -14,16 +14,36 @@ mkfs.fat -F 32 "${IMG_PATH}"
 mkdir -p "${ESP_DIR}/EFI/BOOT"
 cp -f "${EFI_PATH}" "${ESP_DIR}/EFI/BOOT/BOOTX64.EFI"
 
-mkdir -p "${ESP_DIR}/kernel"
-cp -f "${RUN_DIR}/kernel.elf" "${ESP_DIR}/kernel/kernel.elf"
-cp -f "${RUN_DIR}/init.elf" "${ESP_DIR}/kernel/init.elf"
+# TFTP=1: serve the kernel and init from QEMU's built-in TFTP server instead of the ESP
+NET_ARGS=(-net none)
+if [[ "${TFTP:-0}" == "1" ]]; then
+  TFTP_DIR="${RUN_DIR}/tftp"
+  mkdir -p "${TFTP_DIR}/kernel"
+  cp -f "${RUN_DIR}/kernel.elf" "${TFTP_DIR}/kernel/kernel.elf"
+  cp -f "${RUN_DIR}/init.elf" "${TFTP_DIR}/kernel/init.elf"
+  if [[ -f "${RUN_DIR}/initrd.tar" ]]; then
+    cp -f "${RUN_DIR}/initrd.tar" "${TFTP_DIR}/kernel/initrd.tar"
+  fi
+
+  mkdir -p "${ESP_DIR}/a9nloader"
+  echo "volume=tftp" > "${ESP_DIR}/a9nloader/loader.conf"
+
+  NET_ARGS=(-netdev "user,id=net0,tftp=${TFTP_DIR}" -device virtio-net-pci,netdev=net0)
+else
+  mkdir -p "${ESP_DIR}/kernel"
+  cp -f "${RUN_DIR}/kernel.elf" "${ESP_DIR}/kernel/kernel.elf"
+  cp -f "${RUN_DIR}/init.elf" "${ESP_DIR}/kernel/init.elf"
+  if [[ -f "${RUN_DIR}/initrd.tar" ]]; then
+    cp -f "${RUN_DIR}/initrd.tar" "${ESP_DIR}/kernel/initrd.tar"
+  fi
+fi
 
 mcopy -i "${IMG_PATH}" -s "${ESP_DIR}"/* ::/
 
 rm -rf "${ESP_DIR}"
 
 exec qemu-system-x86_64 \
-  -m 512 -cpu max -net none -serial mon:stdio \
+  -m 512 -cpu max "${NET_ARGS[@]}" -serial mon:stdio \
   -drive if=pflash,format=raw,readonly=on,file="${RUN_DIR}/OVMF_CODE.fd" \
   -drive if=pflash,format=raw,file="${RUN_DIR}/OVMF_VARS.fd" \
   -drive format=raw,file="${IMG_PATH}"